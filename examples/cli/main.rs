@@ -3,17 +3,61 @@
 //! This is more sample code than anything else because each command requires
 //! a separate invocation, including connecting to and disconnecting from a store.
 //! Invoke this command with no arguments to see usage information.
-use clap::{Args, Parser};
+use clap::{Args, CommandFactory, Parser};
 use std::collections::HashMap;
 
 use keyring::{
-    internalize, release_store, store_info, use_named_store, use_named_store_with_modifiers,
+    ConflictPolicy, ImportOptions, MigrateOptions, MigrationReport, available_stores,
+    default_store_info, enable_audit_log, export, import, internalize, migrate, parse_modifiers,
+    parse_store_spec, release_store, set_operation_timeout, store_info, store_modifiers,
+    use_named_store, use_named_store_with_modifiers, use_store_profile,
 };
 use keyring_core::{Entry, Error, Result};
 
 fn main() {
-    let args: Cli = Cli::parse();
-    set_store(&args);
+    let mut args: Cli = Cli::parse();
+    if args.help_modifiers {
+        print_store_modifiers(&args.module);
+        return;
+    }
+    if args.command.is_none() {
+        Cli::command()
+            .bin_name("keyring-cli")
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required unless --help-modifiers is given",
+            )
+            .exit();
+    }
+    if let Some(ms) = args.timeout_ms {
+        set_operation_timeout(Some(std::time::Duration::from_millis(ms)));
+    }
+    if let Command::Stores = args.subcommand() {
+        print_available_stores();
+        return;
+    }
+    match args.subcommand() {
+        Command::Migrate { .. } => return migrate_credentials(&args),
+        Command::Export { .. } => return export_credentials(&args),
+        Command::Import { .. } => return import_credentials(&args),
+        _ => {}
+    }
+    if let Some(path) = &args.audit_log
+        && let Err(err) = enable_audit_log(path)
+    {
+        println!("Couldn't enable the audit log: {err}");
+        std::process::exit(1);
+    }
+    if let Some(profile) = &args.profile {
+        let profile = use_store_profile(&args.profile_file, profile).unwrap_or_else(|err| {
+            println!("{err}");
+            std::process::exit(1);
+        });
+        println!("Using the {} credential store", profile.store);
+        args.service = profile.service_name(&args.service);
+    } else {
+        set_store(&args);
+    }
     let entry = match args.entry_for() {
         Ok(entry) => entry,
         Err(err) => {
@@ -22,10 +66,19 @@ fn main() {
             std::process::exit(1)
         }
     };
-    match &args.command {
-        Command::Info => {
-            println!("Store info: {}", store_info());
-            println!("Entry info: {entry:?}");
+    match args.subcommand() {
+        Command::Stores => panic!("Can't happen: stores command doesn't use a store"),
+        Command::Migrate { .. } | Command::Export { .. } | Command::Import { .. } => {
+            panic!("Can't happen: this command doesn't use the default store")
+        }
+        Command::Info { json } => {
+            if *json {
+                let info = default_store_info();
+                println!("{}", serde_json::to_string_pretty(&info).unwrap());
+            } else {
+                println!("Store info: {}", store_info());
+                println!("Entry info: {entry:?}");
+            }
         }
         Command::Set { .. } => {
             let value = args.read_value_to_set();
@@ -85,25 +138,182 @@ fn main() {
 }
 
 fn set_store(args: &Cli) {
-    let (name, rest) = args
-        .module
-        .split_once(':')
-        .unwrap_or((args.module.as_str(), ""));
-    if rest.is_empty() {
-        use_named_store(name).unwrap_or_else(|err| {
+    let (name, modifiers) = parse_store_spec(&args.module).unwrap_or_else(|err| {
+        println!("Sorry, the store specification is not valid: {err}");
+        std::process::exit(1);
+    });
+    if modifiers.is_empty() {
+        use_named_store(&name).unwrap_or_else(|err| {
             println!("{err}");
             std::process::exit(1);
         });
         println!("Using the {name} credential store");
     } else {
-        let modifiers = Some(parse_attributes(rest.to_string()));
-        let mods = internalize(modifiers.as_ref());
-        use_named_store_with_modifiers(name, &mods).unwrap_or_else(|err| {
+        let mods = internalize(Some(&modifiers));
+        use_named_store_with_modifiers(&name, &mods).unwrap_or_else(|err| {
             println!("{err}");
             std::process::exit(1);
         });
         println!("Using the {name} credential store with the following attributes:");
-        print_attributes(modifiers.as_ref().unwrap());
+        print_attributes(&modifiers);
+    }
+}
+
+fn print_available_stores() {
+    println!("Credential stores on this host:");
+    for store in available_stores() {
+        let status = if store.is_available() {
+            "available".to_string()
+        } else if !store.compiled_in {
+            "not compiled in".to_string()
+        } else if !store.platform_supported {
+            format!("not supported: {}", store.error.unwrap_or_default())
+        } else if store.needs_configuration {
+            match store.error {
+                Some(error) => format!("needs configuration: {error}"),
+                None => "needs configuration".to_string(),
+            }
+        } else {
+            format!("unavailable: {}", store.error.unwrap_or_default())
+        };
+        println!("{:>20} ({}): {status}", store.name, store.description);
+    }
+}
+
+fn migrate_credentials(args: &Cli) {
+    let Command::Migrate {
+        to,
+        dry_run,
+        overwrite,
+        delete_source,
+        query,
+    } = args.subcommand()
+    else {
+        panic!("Can't happen: not a migrate command")
+    };
+    let spec = query.as_ref().map(|query| parse_attributes(query.clone()));
+    let options = MigrateOptions {
+        dry_run: *dry_run,
+        on_conflict: conflict_policy(*overwrite),
+        delete_source: *delete_source,
+    };
+    let report = match migrate(&args.module, to, &internalize(spec.as_ref()), &options) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Couldn't migrate from '{}' to '{to}': {err}", args.module);
+            std::process::exit(1);
+        }
+    };
+    print_report(&report, "migrate", "Migrated");
+}
+
+fn export_credentials(args: &Cli) {
+    let Command::Export {
+        file,
+        passphrase,
+        query,
+    } = args.subcommand()
+    else {
+        panic!("Can't happen: not an export command")
+    };
+    let spec = query.as_ref().map(|query| parse_attributes(query.clone()));
+    let passphrase = read_passphrase(passphrase);
+    match export(&args.module, &internalize(spec.as_ref()), file, &passphrase) {
+        Ok(count) => println!("Exported {count} credential(s) to '{file}'"),
+        Err(err) => {
+            println!("Couldn't export from '{}': {err}", args.module);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn import_credentials(args: &Cli) {
+    let Command::Import {
+        file,
+        passphrase,
+        dry_run,
+        overwrite,
+    } = args.subcommand()
+    else {
+        panic!("Can't happen: not an import command")
+    };
+    let passphrase = read_passphrase(passphrase);
+    let options = ImportOptions {
+        dry_run: *dry_run,
+        on_conflict: conflict_policy(*overwrite),
+    };
+    let report = match import(file, &args.module, &passphrase, &options) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Couldn't import '{file}' into '{}': {err}", args.module);
+            std::process::exit(1);
+        }
+    };
+    print_report(&report, "import", "Imported");
+}
+
+fn conflict_policy(overwrite: bool) -> ConflictPolicy {
+    if overwrite {
+        ConflictPolicy::Overwrite
+    } else {
+        ConflictPolicy::Skip
+    }
+}
+
+fn read_passphrase(input: &Option<String>) -> String {
+    if let Some(input) = input {
+        input.clone()
+    } else {
+        rpassword::prompt_password("Archive passphrase: ").unwrap_or_else(|_| String::new())
+    }
+}
+
+fn print_report(report: &MigrationReport, action: &str, done: &str) {
+    if report.entries.is_empty() {
+        println!("No credentials found to {action}");
+    }
+    let verb = if report.dry_run {
+        format!("Would {action}")
+    } else {
+        done.to_string()
+    };
+    for entry in &report.entries {
+        let description = format!("{}@{}", entry.user, entry.service);
+        println!("{verb} '{description}': {}", entry.outcome);
+        for warning in &entry.warnings {
+            println!("    warning: {warning}");
+        }
+    }
+    if report.failures() > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn print_store_modifiers(module: &str) {
+    let (name, _) = parse_store_spec(module).unwrap_or_else(|err| {
+        println!("Sorry, the store specification is not valid: {err}");
+        std::process::exit(1);
+    });
+    match store_modifiers(&name) {
+        Ok(Some(modifiers)) if modifiers.is_empty() => {
+            println!("The {name} credential store takes no modifiers");
+        }
+        Ok(Some(modifiers)) => {
+            println!("Modifiers accepted by the {name} credential store:");
+            for modifier in modifiers {
+                let default = match &modifier.default {
+                    Some(default) => format!(", default {default:?}"),
+                    None => String::new(),
+                };
+                println!("    {} ({}{default})", modifier.key, modifier.kind);
+                println!("        {}", modifier.description);
+            }
+        }
+        Ok(None) => println!("The {name} credential store doesn't describe its modifiers"),
+        Err(err) => {
+            println!("{err}");
+            std::process::exit(1);
+        }
     }
 }
 
@@ -112,9 +322,24 @@ fn set_store(args: &Cli) {
 /// Keyring CLI: A command-line interface to platform secure storage
 pub struct Cli {
     #[clap(global = true, short, long, value_parser, default_value = "sample")]
-    /// The credential store module to use.
+    /// The credential store module to use: the name of a registered
+    /// store, optionally followed by `:key1=val1,key2=val2` modifiers.
     pub module: String,
 
+    #[clap(global = true, long, value_parser)]
+    /// A profile (from the profile file) that specifies the credential
+    /// store module to use. If given, the module argument is ignored.
+    pub profile: Option<String>,
+
+    #[clap(
+        global = true,
+        long,
+        value_parser,
+        default_value = "keyring-profiles.ron"
+    )]
+    /// The file containing credential store profiles.
+    pub profile_file: String,
+
     #[clap(
         global = true,
         short,
@@ -135,14 +360,34 @@ pub struct Cli {
     /// The user for the entry.
     pub user: String,
 
+    #[clap(global = true, long, value_parser)]
+    /// Give up on credential store operations that take longer
+    /// than this many milliseconds.
+    pub timeout_ms: Option<u64>,
+
+    #[clap(global = true, long, value_parser)]
+    /// Append a record of every operation on the credential store
+    /// to this file (in JSON lines format).
+    pub audit_log: Option<String>,
+
+    #[clap(long, action)]
+    /// List the modifiers accepted by the module's store, then exit.
+    pub help_modifiers: bool,
+
     #[clap(subcommand)]
-    pub command: Command,
+    pub command: Option<Command>,
 }
 
 #[derive(Debug, Parser)]
 pub enum Command {
     /// Show info about the store and entry in use.
-    Info,
+    Info {
+        #[clap(long, action)]
+        /// Print the store info as JSON.
+        json: bool,
+    },
+    /// Show which credential stores can be used on this host.
+    Stores,
     /// Set the password/secret or update the attributes in the secure store
     Set {
         #[command(flatten)]
@@ -172,6 +417,65 @@ pub enum Command {
         /// The query spec for the search: key1=value1,key2=value2
         query: Option<String>,
     },
+    /// Copy the credentials matching a search from the module's
+    /// store to another store.
+    Migrate {
+        #[clap(long, value_parser)]
+        /// The store to copy to, in the same syntax as the module argument.
+        to: String,
+
+        #[clap(long, action)]
+        /// Report what would be copied without changing either store.
+        dry_run: bool,
+
+        #[clap(long, action)]
+        /// Replace credentials that already exist in the target store.
+        overwrite: bool,
+
+        #[clap(long, action)]
+        /// Delete credentials from the module's store once they are copied.
+        delete_source: bool,
+
+        #[clap(value_parser)]
+        /// The query spec for the search: key1=value1,key2=value2
+        query: Option<String>,
+    },
+    /// Export the credentials matching a search in the module's
+    /// store to an encrypted archive file.
+    Export {
+        #[clap(long, value_parser)]
+        /// The archive file to write.
+        file: String,
+
+        #[clap(long, value_parser)]
+        /// The passphrase to encrypt the archive with. If not
+        /// specified, it will be read interactively from the terminal.
+        passphrase: Option<String>,
+
+        #[clap(value_parser)]
+        /// The query spec for the search: key1=value1,key2=value2
+        query: Option<String>,
+    },
+    /// Import the credentials in an encrypted archive file
+    /// into the module's store.
+    Import {
+        #[clap(long, value_parser)]
+        /// The archive file to read.
+        file: String,
+
+        #[clap(long, value_parser)]
+        /// The passphrase the archive was encrypted with. If not
+        /// specified, it will be read interactively from the terminal.
+        passphrase: Option<String>,
+
+        #[clap(long, action)]
+        /// Report what would be imported without changing the store.
+        dry_run: bool,
+
+        #[clap(long, action)]
+        /// Replace credentials that already exist in the store.
+        overwrite: bool,
+    },
 }
 
 #[derive(Debug, Args)]
@@ -202,6 +506,12 @@ enum Value {
 }
 
 impl Cli {
+    fn subcommand(&self) -> &Command {
+        self.command
+            .as_ref()
+            .expect("Can't happen: the subcommand is checked in main")
+    }
+
     fn description(&self) -> String {
         format!("{}@{}", self.user, self.service)
     }
@@ -222,8 +532,12 @@ impl Cli {
                     println!("{: >4}: {cred:?}", i + 1);
                 }
             }
-            err => match &self.command {
-                Command::Info => panic!("Can't happen: info command should never fail"),
+            err => match self.subcommand() {
+                Command::Info { .. } => panic!("Can't happen: info command should never fail"),
+                Command::Stores => panic!("Can't happen: stores command should never fail"),
+                Command::Migrate { .. } | Command::Export { .. } | Command::Import { .. } => {
+                    panic!("Can't happen: this command doesn't use an entry")
+                }
                 Command::Set { .. } => {
                     println!("Couldn't set credential data for '{description}': {err:?}");
                 }
@@ -256,8 +570,16 @@ impl Cli {
 
     fn success_message_for(&self, value: &Value) {
         let description = self.description();
-        match &self.command {
-            Command::Info => panic!("Can't happen: info command should not invoke success message"),
+        match self.subcommand() {
+            Command::Info { .. } => {
+                panic!("Can't happen: info command should not invoke success message")
+            }
+            Command::Stores => {
+                panic!("Can't happen: stores command should not invoke success message")
+            }
+            Command::Migrate { .. } | Command::Export { .. } | Command::Import { .. } => {
+                panic!("Can't happen: this command should not invoke success message")
+            }
             Command::Set { .. } => match value {
                 Value::Secret(secret) => {
                     let secret = secret_string(secret);
@@ -330,7 +652,7 @@ impl Cli {
     }
 
     fn read_value_to_set(&self) -> Value {
-        if let Command::Set { what, input } = &self.command {
+        if let Command::Set { what, input } = self.subcommand() {
             if what.password {
                 Value::Password(read_password(input))
             } else if what.blob {
@@ -398,18 +720,8 @@ fn read_and_parse_attributes(input: &Option<String>) -> HashMap<String, String>
 }
 
 fn parse_attributes(input: String) -> HashMap<String, String> {
-    let mut attributes = HashMap::new();
-    if input.is_empty() {
-        return attributes;
-    }
-    let parts = input.split(',');
-    for s in parts.into_iter() {
-        let parts: Vec<&str> = s.split("=").collect();
-        if parts.len() != 2 || parts[0].is_empty() {
-            println!("Sorry, this part of the attributes string is not a key=val pair: {s}");
-            std::process::exit(1);
-        }
-        attributes.insert(parts[0].to_string(), parts[1].to_string());
-    }
-    attributes
+    parse_modifiers(&input).unwrap_or_else(|err| {
+        println!("Sorry, the attributes string is not valid: {err}");
+        std::process::exit(1);
+    })
 }
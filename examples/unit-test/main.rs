@@ -7,20 +7,45 @@ expected by `keyring-core`.
 
 */
 
-use keyring::{NAMED_STORES, release_store, use_named_store};
+use std::collections::HashMap;
+
+use keyring::{available_stores, release_store, use_named_store, use_named_store_with_modifiers};
 use keyring_core::Error;
 
 mod runner;
 
+/// Stores that can't be written, so the tests (which write credentials) can't pass on them.
+const READ_ONLY_STORES: [&str; 2] = ["env", "systemd-creds"];
+
 fn main() {
-    println!("Running tests on {} stores...", NAMED_STORES.len());
-    for store in NAMED_STORES {
-        match use_named_store(store) {
+    let stores = available_stores();
+    // the dir store's default root is the real secrets directory, so it gets a scratch one
+    let dir_root = std::env::temp_dir().join(format!("keyring-unit-test-{}", fastrand::u64(..)));
+    println!("Running tests on {} stores...", stores.len());
+    for availability in &stores {
+        let store = availability.name.as_str();
+        if availability.needs_configuration {
+            println!("\nSkipping store '{store}': it can't be used without configuration");
+            continue;
+        }
+        if READ_ONLY_STORES.contains(&store) {
+            println!("\nSkipping store '{store}': it's read-only");
+            continue;
+        }
+        let result = match store {
+            "dir" => use_named_store_with_modifiers(
+                store,
+                &HashMap::from([("root", dir_root.to_str().unwrap())]),
+            ),
+            _ => use_named_store(store),
+        };
+        match result {
             Ok(_) => run_tests(store),
             Err(Error::NotSupportedByStore(s)) => println!("\nSkipping store '{store}': {s}"),
             Err(err) => println!("\nCouldn't instantiate store '{store}': {err:?}"),
         }
     }
+    let _ = std::fs::remove_dir_all(&dir_root);
     println!("\nFinished running tests.");
 }
 
@@ -10,9 +10,44 @@
 //! Application](https://github.com/open-source-cooperative/keyring-demo).
 //!
 //! For each available keyring-compatible credential store (other than mock),
-//! this module defines a `use_...` function which sets that store
-//! as the default credential store. It also gives that store a name
-//! in the [use_named_store] convenience function.
+//! this module defines a `build_...` function which builds that store and
+//! a `use_...` function which sets that store as the default credential store.
+//! It also gives that store a name in the [build_named_store] and
+//! [use_named_store] convenience functions.
+//!
+//! Because the `build_...` functions don't touch the default store, a process can
+//! use them to work with more than one store at a time: see [new_entry_in] and
+//! [search_in] for creating and searching for entries in a specific store,
+//! and [migrate] for copying credentials from one store to another.
+//! To back up the contents of a store, use [export] to write them to
+//! an encrypted archive file and [import] to restore them into any store.
+//!
+//! Store names are kept in a registry which comes pre-loaded with the
+//! [NAMED_STORES]. Applications that have their own credential stores can
+//! add them to the registry with [register_store], after which they can be
+//! chosen by name just like the built-in stores. Each store can declare the
+//! modifiers it accepts (see [store_modifiers]), and modifiers that a store
+//! doesn't accept are rejected before the store is built. Every store also
+//! accepts modifiers that wrap it in a store adding some behavior: for example,
+//! `timeout-ms=5000` gives up on operations that take too long (see [TimeoutStore]),
+//! `retry-attempts=3` retries operations that fail transiently (see [RetryingStore]),
+//! `cache-ttl-secs=30` caches the secrets read from the store (see [CachingStore]),
+//! `namespace=staging` keeps the store's entries apart from those of other
+//! namespaces (see [NamespaceStore]), and `read-only=true` refuses any change
+//! to the store (see [ReadOnlyStore]).
+//! To keep an audit log of the operations on every store chosen by name
+//! (including by [use_native_store]), call [enable_audit_log].
+//!
+//! Async programs can use `AsyncEntry` (available with the `async` feature),
+//! which runs store operations on a thread pool rather than on the executor.
+//!
+//! Programs that want their store chosen at runtime rather than at build time can
+//! call [use_store_from_env], which reads a store specification (in the same
+//! `name:key=val,...` syntax accepted by the CLI's `--module` flag) from the
+//! `KEYRING_STORE` environment variable.
+//! Programs that run against different stores in different environments can
+//! instead keep named profiles in a configuration file and choose among them
+//! with [use_store_profile].
 //!
 //! As developers make new credential store modules available,
 //! they are encouraged to submit a pull request that adds a connection here for their module,
@@ -22,59 +57,1063 @@
 
 use std::collections::HashMap;
 use std::format;
+#[cfg(feature = "audit")]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock, Weak};
+use std::time::Duration;
 
-use keyring_core::{Error, Result, get_default_store, set_default_store, unset_default_store};
+use keyring_core::{
+    CredentialPersistence, CredentialStore, Entry, Error, Result, get_default_store,
+    set_default_store, unset_default_store,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "async")]
+mod async_entry;
+#[cfg(feature = "async")]
+pub use async_entry::{AsyncEntry, use_named_store_async, use_named_store_with_modifiers_async};
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "archive")]
+pub use archive::{Archive, ArchivedCredential, ImportOptions, export, import};
+#[cfg(feature = "audit")]
+mod audit;
+#[cfg(feature = "audit")]
+pub use audit::AuditingStore;
+mod cache;
+pub use cache::{CachingStore, DEFAULT_CACHE_MAX_ENTRIES};
+mod dir;
+pub use dir::{DEFAULT_DIR_ROOT, DirLayout, DirStore};
+#[cfg(feature = "env")]
+mod env;
+#[cfg(feature = "env")]
+pub use env::{DEFAULT_ENV_TEMPLATE, EnvStore};
+mod exec;
+pub use exec::ExecStore;
+#[cfg(feature = "kdbx")]
+mod kdbx;
+#[cfg(feature = "kdbx")]
+pub use kdbx::KdbxStore;
+mod layered;
+pub use layered::{LayeredStore, ReadOrder, WriteTarget};
+mod migrate;
+pub use migrate::{
+    ConflictPolicy, MigrateOptions, MigratedEntry, MigrationOutcome, MigrationReport, migrate,
+    migrate_between,
+};
+mod mirror;
+pub use mirror::{MirrorPolicy, MirrorStore};
+mod namespace;
+pub use namespace::{DEFAULT_NAMESPACE_SEPARATOR, NamespaceStore};
+mod modifiers;
+pub use modifiers::{ModifierKind, ModifierSpec};
+#[cfg(feature = "profile")]
+mod profile;
+#[cfg(feature = "profile")]
+pub use profile::{StoreConfig, StoreProfile, use_store_profile};
+mod read_only;
+pub use read_only::ReadOnlyStore;
+mod retry;
+pub use retry::{ERROR_VARIANTS, RetryPolicy, RetryingStore};
+mod timeout;
+pub use timeout::{OperationTimedOut, TimeoutStore, is_timeout};
 
 /// An alphabetic list of known credential stores.
-pub const NAMED_STORES: [&str; 9] = [
+///
+/// These are the stores that are pre-registered in the store registry
+/// (see [register_store]), including the composite `layered` and `mirror`
+/// stores (see [LayeredStore] and [MirrorStore]).
+pub const NAMED_STORES: [&str; 16] = [
     "android",
+    "dbus-secret-service",
+    "dir",
+    "env",
+    "exec",
+    "kdbx",
     "keychain",
     "keyutils",
+    "layered",
+    "mirror",
     "protected",
     "sample",
     "secret-service",
-    "dbus-secret-service",
     "sqlite",
+    "systemd-creds",
     "windows",
 ];
 
-/// Set the default store to one of the known stores in its default configuration.
+/// A function that builds a store, given the modifiers for the store's builder.
+pub type StoreConstructor =
+    Arc<dyn Fn(&HashMap<&str, &str>) -> Result<Arc<CredentialStore>> + Send + Sync>;
+
+/// A store known to the registry.
+struct RegisteredStore {
+    name: String,
+    description: String,
+    compiled_in: bool,
+    /// The modifiers the store accepts, if it has declared them.
+    modifiers: Option<Vec<ModifierSpec>>,
+    constructor: StoreConstructor,
+    probe: Probe,
+}
+
+/// How [available_stores] checks whether a registered store can be used.
+#[derive(Clone, Copy)]
+enum Probe {
+    /// Build the store with these modifiers, and then drop it.
+    Build(&'static [(&'static str, &'static str)]),
+    /// Don't build the store, as it can't be built without being configured.
+    NeedsConfiguration,
+}
+
+/// How to check each of the built-in stores, without leaving anything behind.
+fn builtin_probe(name: &str) -> Probe {
+    match name {
+        // in memory, rather than persisted to the temporary directory
+        "sample" => Probe::Build(&[("persist", "false")]),
+        // in memory, rather than creating a database at the default path
+        "sqlite" => Probe::Build(&[("vfs", "memory")]),
+        "exec" | "kdbx" | "layered" | "mirror" => Probe::NeedsConfiguration,
+        _ => Probe::Build(&[]),
+    }
+}
+
+static REGISTRY: LazyLock<RwLock<Vec<RegisteredStore>>> =
+    LazyLock::new(|| RwLock::new(builtin_stores()));
+
+fn builtin_stores() -> Vec<RegisteredStore> {
+    fn entry(
+        name: &str,
+        description: &str,
+        compiled_in: bool,
+        constructor: fn(&HashMap<&str, &str>) -> Result<Arc<CredentialStore>>,
+    ) -> RegisteredStore {
+        RegisteredStore {
+            name: name.to_string(),
+            description: description.to_string(),
+            compiled_in,
+            modifiers: Some(modifiers::builtin_modifiers(name)),
+            constructor: Arc::new(constructor),
+            probe: builtin_probe(name),
+        }
+    }
+    vec![
+        entry(
+            "android",
+            "Android Shared Preferences",
+            cfg!(target_os = "android"),
+            build_android_native_store,
+        ),
+        entry(
+            "dbus-secret-service",
+            "Secret Service via libdbus",
+            cfg!(all(
+                unix,
+                not(any(
+                    target_os = "macos",
+                    target_os = "ios",
+                    target_os = "android"
+                ))
+            )),
+            build_dbus_secret_service_store,
+        ),
+        entry(
+            "dir",
+            "one file per credential under a directory",
+            true,
+            build_dir_store,
+        ),
+        entry(
+            "env",
+            "environment variables",
+            cfg!(feature = "env"),
+            build_env_store,
+        ),
+        entry(
+            "exec",
+            "a helper program, as with git credential helpers",
+            true,
+            build_exec_store,
+        ),
+        entry(
+            "kdbx",
+            "KeePass (KDBX4) database file",
+            cfg!(feature = "kdbx"),
+            build_kdbx_store,
+        ),
+        entry(
+            "keychain",
+            "macOS Keychain Services",
+            cfg!(target_os = "macos"),
+            build_apple_keychain_store,
+        ),
+        entry(
+            "keyutils",
+            "Linux kernel keyutils",
+            cfg!(target_os = "linux"),
+            build_linux_keyutils_store,
+        ),
+        entry(
+            "protected",
+            "iOS/macOS Protected Data",
+            cfg!(any(target_os = "macos", target_os = "ios")),
+            build_apple_protected_store,
+        ),
+        entry(
+            "sample",
+            "keyring-core sample store",
+            true,
+            build_named_sample_store,
+        ),
+        entry(
+            "secret-service",
+            "Secret Service via zbus",
+            cfg!(all(
+                unix,
+                not(any(
+                    target_os = "macos",
+                    target_os = "ios",
+                    target_os = "android"
+                ))
+            )),
+            build_zbus_secret_service_store,
+        ),
+        entry(
+            "sqlite",
+            "encrypted sqlite (Turso) database",
+            cfg!(not(any(target_os = "ios", target_os = "android"))),
+            build_sqlite_store,
+        ),
+        entry(
+            "systemd-creds",
+            "systemd service credentials (read-only)",
+            cfg!(target_os = "linux"),
+            build_systemd_creds_store,
+        ),
+        entry(
+            "windows",
+            "Windows Credential Manager",
+            cfg!(target_os = "windows"),
+            build_windows_native_store,
+        ),
+        entry(
+            "layered",
+            "reads from two stores, writes to one",
+            true,
+            |mods| Ok(LayeredStore::new_with_modifiers(mods)?),
+        ),
+        entry(
+            "mirror",
+            "mirrors changes to several stores",
+            true,
+            |mods| Ok(MirrorStore::new_with_modifiers(mods)?),
+        ),
+    ]
+}
+
+/// Store names are case-insensitive, and `zbus-secret-service` is an alias
+/// for `secret-service`.
+fn canonical_name(name: &str) -> String {
+    let name = name.to_lowercase();
+    if name == "zbus-secret-service" {
+        "secret-service".to_string()
+    } else {
+        name
+    }
+}
+
+fn registry_read() -> std::sync::RwLockReadGuard<'static, Vec<RegisteredStore>> {
+    REGISTRY
+        .read()
+        .expect("Poisoned RwLock in keyring::cli store registry: please report a bug!")
+}
+
+fn registry_write() -> std::sync::RwLockWriteGuard<'static, Vec<RegisteredStore>> {
+    REGISTRY
+        .write()
+        .expect("Poisoned RwLock in keyring::cli store registry: please report a bug!")
+}
+
+/// The name and modifiers under which a store was chosen.
+struct Selection {
+    store: Weak<CredentialStore>,
+    name: String,
+    modifiers: HashMap<String, String>,
+}
+
+static SELECTIONS: RwLock<Vec<Selection>> = RwLock::new(Vec::new());
+
+/// The modifiers whose values are secrets, such as encryption keys and passwords.
 ///
-/// Gives an `Invalid` error if the store name is not known.
+/// These are matched against the last dot-separated part of a modifier key,
+/// so that the prefixed forms taken by the layered and mirror stores (such
+/// as `primary.hexkey`) are covered as well.
+const SECRET_MODIFIERS: &[&str] = &["hexkey", "encryption-hexkey", "password"];
+
+/// What the value of a secret modifier is recorded as.
+const REDACTED_VALUE: &str = "<redacted>";
+
+fn is_secret_modifier(key: &str) -> bool {
+    let last = key.rsplit('.').next().unwrap_or(key).replace('_', "-");
+    SECRET_MODIFIERS.contains(&last.as_str())
+}
+
+/// Record the name and modifiers a store was chosen with, redacting secret values.
+fn record_selection(store: &Arc<CredentialStore>, name: &str, modifiers: &HashMap<&str, &str>) {
+    let modifiers = modifiers
+        .iter()
+        .map(|(key, value)| {
+            let value = if is_secret_modifier(key) {
+                REDACTED_VALUE
+            } else {
+                value
+            };
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+    let mut guard = SELECTIONS
+        .write()
+        .expect("Poisoned RwLock in keyring::cli store selections: please report a bug!");
+    // forget about stores that have been dropped or are being re-recorded
+    guard.retain(|s| s.store.strong_count() > 0 && !is_same_store(&s.store, store));
+    guard.push(Selection {
+        store: Arc::downgrade(store),
+        name: name.to_string(),
+        modifiers,
+    });
+}
+
+/// Record that a wrapper around a store was chosen as the store was, plus one modifier.
+fn record_wrapped_selection(
+    store: &Arc<CredentialStore>,
+    wrapped: &Arc<CredentialStore>,
+    key: &str,
+    value: &str,
+) {
+    if let Some((name, mut modifiers)) = selection_of(store) {
+        modifiers.insert(key.to_string(), value.to_string());
+        record_selection(wrapped, &name, &internalize(Some(&modifiers)));
+    }
+}
+
+/// Look up the name and modifiers a store was chosen with, if it was chosen by name.
+fn selection_of(store: &Arc<CredentialStore>) -> Option<(String, HashMap<String, String>)> {
+    let guard = SELECTIONS
+        .read()
+        .expect("Poisoned RwLock in keyring::cli store selections: please report a bug!");
+    guard
+        .iter()
+        .find(|s| is_same_store(&s.store, store))
+        .map(|s| (s.name.clone(), s.modifiers.clone()))
+}
+
+fn is_same_store(weak: &Weak<CredentialStore>, store: &Arc<CredentialStore>) -> bool {
+    std::ptr::addr_eq(weak.as_ptr(), Arc::as_ptr(store))
+}
+
+/// Register a credential store under the given name.
 ///
-/// Returns any error returned from store creation.
-pub fn use_named_store(name: &str) -> Result<()> {
-    if name.to_lowercase().as_str() == "sample" {
-        use_sample_store(&HashMap::from([("persist", "true")]))
-    } else {
-        use_named_store_with_modifiers(name, &HashMap::new())
+/// Once registered, the store can be chosen by name via [use_named_store] and
+/// [use_named_store_with_modifiers], and it is listed by [registered_stores].
+/// The constructor is passed the modifiers given by the caller, and returns
+/// the built store (as the `build_...` functions in this module do).
+///
+/// Stores registered this way don't declare which modifiers they accept,
+/// so all modifiers are passed to the constructor unchecked. Use
+/// [register_store_with_modifiers] to have them checked.
+///
+/// Gives an `Invalid` error if the name is empty, contains a `:` or `,`
+/// (which would make it unusable in a store specification), or is already registered.
+pub fn register_store<F>(name: &str, description: &str, constructor: F) -> Result<()>
+where
+    F: Fn(&HashMap<&str, &str>) -> Result<Arc<CredentialStore>> + Send + Sync + 'static,
+{
+    register(name, description, None, Arc::new(constructor))
+}
+
+/// Register a credential store under the given name, declaring the modifiers it accepts.
+///
+/// This is like [register_store], except that modifiers given for the store are
+/// checked against the declared ones (see [validate_modifiers]) before the
+/// constructor is called, and they are listed by [store_modifiers].
+pub fn register_store_with_modifiers<F>(
+    name: &str,
+    description: &str,
+    modifiers: Vec<ModifierSpec>,
+    constructor: F,
+) -> Result<()>
+where
+    F: Fn(&HashMap<&str, &str>) -> Result<Arc<CredentialStore>> + Send + Sync + 'static,
+{
+    register(name, description, Some(modifiers), Arc::new(constructor))
+}
+
+fn register(
+    name: &str,
+    description: &str,
+    modifiers: Option<Vec<ModifierSpec>>,
+    constructor: StoreConstructor,
+) -> Result<()> {
+    if name.is_empty() || name.contains([':', ',']) {
+        return Err(Error::Invalid(
+            name.to_string(),
+            "store names must be non-empty and cannot contain ':' or ','".to_string(),
+        ));
+    }
+    let name = canonical_name(name);
+    let mut registry = registry_write();
+    if registry.iter().any(|r| r.name == name) {
+        return Err(Error::Invalid(name, "is already registered".to_string()));
     }
+    registry.push(RegisteredStore {
+        name,
+        description: description.to_string(),
+        compiled_in: true,
+        modifiers,
+        constructor,
+        probe: Probe::Build(&[]),
+    });
+    Ok(())
 }
 
-/// Set the default store to one of the known stores in the specified configuration.
+/// Remove a store from the registry.
+///
+/// Returns whether a store with that name was registered.
+pub fn unregister_store(name: &str) -> bool {
+    let name = canonical_name(name);
+    let mut registry = registry_write();
+    let before = registry.len();
+    registry.retain(|r| r.name != name);
+    registry.len() != before
+}
+
+/// List the names and descriptions of all registered stores, in registration order.
+///
+/// The built-in stores (see [NAMED_STORES]) come first.
+pub fn registered_stores() -> Vec<(String, String)> {
+    registry_read()
+        .iter()
+        .map(|r| (r.name.clone(), r.description.clone()))
+        .collect()
+}
+
+/// List the modifiers accepted by a registered store.
+///
+/// The list includes the modifiers accepted by every store that wrap it in
+/// another store adding some behavior, such as `cache-ttl-secs` (see [CachingStore]).
+///
+/// Returns `None` if the store was registered without declaring its modifiers
+/// (see [register_store]). Gives an `Invalid` error if the store name is not registered.
+pub fn store_modifiers(name: &str) -> Result<Option<Vec<ModifierSpec>>> {
+    let canonical = canonical_name(name);
+    match registry_read().iter().find(|r| r.name == canonical) {
+        Some(r) => Ok(r.modifiers.clone().map(|mut specs| {
+            specs.extend(modifiers::wrapper_modifiers());
+            specs
+        })),
+        None => Err(unknown_store(name)),
+    }
+}
+
+/// Check modifiers against those accepted by a registered store.
+///
+/// Gives an `Invalid` error for a key that the store doesn't accept (suggesting
+/// the key that was probably meant, if there's a close one), or for a value of
+/// the wrong type. Modifiers for a store that doesn't declare its modifiers
+/// are always accepted, except for those that wrap the store, which are
+/// always checked.
+///
+/// This check is made by [build_named_store_with_modifiers] before the store is built.
+pub fn validate_modifiers(name: &str, modifiers: &HashMap<&str, &str>) -> Result<()> {
+    let canonical = canonical_name(name);
+    match store_modifiers(name)? {
+        Some(specs) => modifiers::check_modifiers(&canonical, &specs, modifiers),
+        None => {
+            let (wrapping, _) = modifiers::split_wrapper_modifiers(modifiers);
+            let specs = modifiers::wrapper_modifiers();
+            modifiers::check_modifiers(&canonical, &specs, &wrapping)
+        }
+    }
+}
+
+fn unknown_store(name: &str) -> Error {
+    let ok = registered_stores()
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Error::Invalid(name.to_string(), format!("must be one of: {ok}"))
+}
+
+/// A report on whether a registered store can be used on this host.
+///
+/// See [available_stores].
+#[derive(Debug, Clone)]
+pub struct StoreAvailability {
+    /// The registered name of the store.
+    pub name: String,
+    /// The registered description of the store.
+    pub description: String,
+    /// Whether the store's module is compiled into this build.
+    pub compiled_in: bool,
+    /// Whether the store is supported on this platform (and in this process).
+    pub platform_supported: bool,
+    /// Whether the store can only be used once it's given modifiers (as the
+    /// `exec` store must be told which helper program to run, for example).
+    pub needs_configuration: bool,
+    /// If the store couldn't be constructed, the text of the error.
+    pub error: Option<String>,
+}
+
+impl StoreAvailability {
+    /// Whether the store can be used without configuring it.
+    pub fn is_available(&self) -> bool {
+        self.error.is_none() && !self.needs_configuration
+    }
+}
+
+/// Report which of the registered stores can actually be used on this host.
+///
+/// Each store that is compiled in is built (and then dropped), in its default
+/// configuration except where that would leave something behind: the `sample`
+/// and `sqlite` stores are built in memory. A store whose construction fails
+/// with a `NotSupportedByStore` error is reported as not supported on this
+/// platform, and one whose construction fails with an `Invalid` error is
+/// reported as needing configuration. The built-in stores that can't be built
+/// without modifiers (`exec`, `kdbx`, `layered`, and `mirror`) aren't built,
+/// and are reported as needing configuration.
+/// The default store is not affected.
+pub fn available_stores() -> Vec<StoreAvailability> {
+    let candidates: Vec<(String, String, bool, StoreConstructor, Probe)> = registry_read()
+        .iter()
+        .map(|r| {
+            let (name, desc) = (r.name.clone(), r.description.clone());
+            (name, desc, r.compiled_in, r.constructor.clone(), r.probe)
+        })
+        .collect();
+    let mut result = Vec::new();
+    for (name, description, compiled_in, constructor, probe) in candidates {
+        let (platform_supported, needs_configuration, error) = if !compiled_in {
+            (
+                false,
+                false,
+                Some("not compiled into this build".to_string()),
+            )
+        } else {
+            match probe {
+                Probe::NeedsConfiguration => (true, true, None),
+                Probe::Build(modifiers) => {
+                    match constructor(&modifiers.iter().copied().collect()) {
+                        Ok(_) => (true, false, None),
+                        Err(Error::NotSupportedByStore(msg)) => (false, false, Some(msg)),
+                        Err(err @ Error::Invalid(_, _)) => (true, true, Some(err.to_string())),
+                        Err(err) => (true, false, Some(err.to_string())),
+                    }
+                }
+            }
+        };
+        result.push(StoreAvailability {
+            name,
+            description,
+            compiled_in,
+            platform_supported,
+            needs_configuration,
+            error,
+        });
+    }
+    result
+}
+
+/// Build one of the known stores in its default configuration.
 ///
-/// The modifiers are passed to the store builder.
+/// The default configuration of each store is the one it gets with no modifiers
+/// (which, for the sample store, is `persist=true`).
 ///
 /// Gives an `Invalid` error if the store name is not known.
 ///
 /// Returns any error returned from store creation.
+pub fn build_named_store(name: &str) -> Result<Arc<CredentialStore>> {
+    build_named_store_with_modifiers(name, &HashMap::new())
+}
+
+/// Build one of the known stores in the specified configuration.
+///
+/// The modifiers are checked by [validate_modifiers] and then passed
+/// to the store's registered constructor, except for those that wrap the store
+/// (see [store_modifiers]), which are applied to the constructed store.
+///
+/// Gives an `Invalid` error if the store name is not registered.
+///
+/// Returns any error returned from store creation.
+pub fn build_named_store_with_modifiers(
+    name: &str,
+    modifiers: &HashMap<&str, &str>,
+) -> Result<Arc<CredentialStore>> {
+    let canonical = canonical_name(name);
+    let constructor = registry_read()
+        .iter()
+        .find(|r| r.name == canonical)
+        .map(|r| r.constructor.clone());
+    let Some(constructor) = constructor else {
+        return Err(unknown_store(name));
+    };
+    validate_modifiers(&canonical, modifiers)?;
+    let (wrapping, building) = modifiers::split_wrapper_modifiers(modifiers);
+    let store = wrap_store(constructor(&building)?, &wrapping)?;
+    record_selection(&store, &canonical, modifiers);
+    Ok(store)
+}
+
+/// The operation timeout, if any, set by [set_operation_timeout].
+static OPERATION_TIMEOUT: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// Set (or, given `None`, clear) the timeout for operations on stores built by name.
+///
+/// Every store built by [build_named_store] and its relatives (including the stores
+/// chosen by [use_named_store] and [use_native_store]) after this call is wrapped
+/// in a [TimeoutStore] with this timeout, unless it is given its own timeout with
+/// the `timeout-ms` modifier (where `timeout-ms=0` means no timeout).
+/// Stores that were already built are not affected.
+///
+/// With the `async` feature, the timeout also applies to creating and searching
+/// for entries with `AsyncEntry`, and to choosing a store with the async
+/// `use_named_store` functions.
+pub fn set_operation_timeout(timeout: Option<Duration>) {
+    *OPERATION_TIMEOUT
+        .write()
+        .expect("Poisoned RwLock in keyring::cli operation timeout: please report a bug!") =
+        timeout;
+}
+
+/// The timeout set by [set_operation_timeout], if any.
+pub fn operation_timeout() -> Option<Duration> {
+    *OPERATION_TIMEOUT
+        .read()
+        .expect("Poisoned RwLock in keyring::cli operation timeout: please report a bug!")
+}
+
+/// The timeout for a newly built store's operations: the one given by its
+/// `timeout-ms` modifier if there is one, else the given global timeout.
+fn store_timeout(
+    wrapping: &HashMap<&str, &str>,
+    global: Option<Duration>,
+) -> Result<Option<Duration>> {
+    let timeout = match modifiers::wrapper_number(wrapping, "timeout-ms")? {
+        Some(ms) => Some(Duration::from_millis(ms)),
+        None => global,
+    };
+    Ok(timeout.filter(|timeout| !timeout.is_zero()))
+}
+
+/// Wrap a newly built store as specified by the modifiers that wrap stores.
+fn wrap_store(
+    store: Arc<CredentialStore>,
+    wrapping: &HashMap<&str, &str>,
+) -> Result<Arc<CredentialStore>> {
+    let mut store = store;
+    if let Some(timeout) = store_timeout(wrapping, operation_timeout())? {
+        store = TimeoutStore::new(store, timeout);
+    }
+    if let Some(attempts) = modifiers::wrapper_number(wrapping, "retry-attempts")?
+        && attempts > 1
+    {
+        let mut policy = RetryPolicy {
+            max_attempts: u32::try_from(attempts).unwrap_or(u32::MAX),
+            ..RetryPolicy::default()
+        };
+        if let Some(ms) = modifiers::wrapper_number(wrapping, "retry-backoff-ms")? {
+            policy.initial_backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = modifiers::wrapper_number(wrapping, "retry-max-backoff-ms")? {
+            policy.max_backoff = Duration::from_millis(ms);
+        }
+        if let Some(list) = modifiers::wrapper_setting(wrapping, "retry-on") {
+            policy.retryable = RetryPolicy::parse_retryable(list)?;
+        }
+        store = RetryingStore::new(store, policy);
+    }
+    if let Some(ttl) = modifiers::wrapper_number(wrapping, "cache-ttl-secs")?
+        && ttl > 0
+    {
+        let max_entries = match modifiers::wrapper_number(wrapping, "cache-max-entries")? {
+            Some(max_entries) => max_entries as usize,
+            None => DEFAULT_CACHE_MAX_ENTRIES,
+        };
+        store = CachingStore::new(store, Duration::from_secs(ttl), max_entries);
+    }
+    if let Some(namespace) = modifiers::wrapper_setting(wrapping, "namespace")
+        && !namespace.is_empty()
+    {
+        let separator = modifiers::wrapper_setting(wrapping, "namespace-separator")
+            .unwrap_or(DEFAULT_NAMESPACE_SEPARATOR);
+        store = NamespaceStore::new(store, namespace, separator);
+    }
+    if modifiers::wrapper_setting(wrapping, "read-only") == Some("true") {
+        store = ReadOnlyStore::new(store);
+    }
+    if let Some(path) = modifiers::wrapper_setting(wrapping, "audit-log") {
+        #[cfg(feature = "audit")]
+        {
+            store = AuditingStore::new(store, path)?;
+        }
+        #[cfg(not(feature = "audit"))]
+        {
+            return Err(Error::Invalid(
+                "audit-log".to_string(),
+                format!("can't log to {path}: audit logs require the audit feature"),
+            ));
+        }
+    }
+    Ok(store)
+}
+
+/// Make the default store read-only, by wrapping it in a [ReadOnlyStore].
+///
+/// This is for processes that choose their store with [use_named_store] (or
+/// [use_native_store], or any other way) and then want to be sure they never
+/// change it. (Stores chosen by name can instead be given the `read-only=true`
+/// modifier.) If the default store is already read-only, it's left as is.
+///
+/// Gives a `NoDefaultStore` error if there is no default store.
+pub fn make_default_store_read_only() -> Result<()> {
+    let store = get_default_store().ok_or(Error::NoDefaultStore)?;
+    if store.as_any().is::<ReadOnlyStore>() {
+        return Ok(());
+    }
+    let read_only: Arc<CredentialStore> = ReadOnlyStore::new(store.clone());
+    record_wrapped_selection(&store, &read_only, "read-only", "true");
+    set_default_store(read_only);
+    Ok(())
+}
+
+/// The audit log, if any, set by [enable_audit_log].
+#[cfg(feature = "audit")]
+static AUDIT_LOG: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Keep an audit log of the operations on the default store.
+///
+/// This wraps the current default store (if any) in an [AuditingStore] that
+/// appends its records to the file at `path`, and does the same for every store
+/// that later becomes the default by [use_named_store] or its relatives (such as
+/// [use_native_store], [use_store_from_env], and [use_store_profile]).
+/// Stores that are already audited (for example, because they were given
+/// the `audit-log` modifier) are left as they are.
+///
+/// Gives an `Invalid` error if the current default store can't be audited
+/// because the file can't be opened. Otherwise, the same error is given
+/// when a store is chosen by name.
+#[cfg(feature = "audit")]
+pub fn enable_audit_log(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref().to_path_buf();
+    if let Some(store) = get_default_store() {
+        set_default_store(audit_store(store, &path)?);
+    }
+    *AUDIT_LOG
+        .write()
+        .expect("Poisoned RwLock in keyring::cli audit log: please report a bug!") = Some(path);
+    Ok(())
+}
+
+/// Stop auditing the stores that become the default (see [enable_audit_log]).
+///
+/// The current default store is not changed, so if it is audited it stays audited.
+#[cfg(feature = "audit")]
+pub fn disable_audit_log() {
+    *AUDIT_LOG
+        .write()
+        .expect("Poisoned RwLock in keyring::cli audit log: please report a bug!") = None;
+}
+
+/// Wrap a store in an [AuditingStore] for the given file, if it isn't already audited.
+#[cfg(feature = "audit")]
+fn audit_store(store: Arc<CredentialStore>, path: &Path) -> Result<Arc<CredentialStore>> {
+    if store.as_any().is::<AuditingStore>() {
+        return Ok(store);
+    }
+    let audited: Arc<CredentialStore> = AuditingStore::new(store.clone(), path)?;
+    record_wrapped_selection(&store, &audited, "audit-log", &path.display().to_string());
+    Ok(audited)
+}
+
+/// Make a store the default store, auditing it if [enable_audit_log] was called.
+fn install_default_store(store: Arc<CredentialStore>) -> Result<()> {
+    #[cfg(feature = "audit")]
+    {
+        let path = AUDIT_LOG
+            .read()
+            .expect("Poisoned RwLock in keyring::cli audit log: please report a bug!")
+            .clone();
+        if let Some(path) = path {
+            set_default_store(audit_store(store, &path)?);
+            return Ok(());
+        }
+    }
+    set_default_store(store);
+    Ok(())
+}
+
+/// Set the default store to one of the known stores in its default configuration.
+///
+/// This sets the default store to the result of [build_named_store], which see.
+pub fn use_named_store(name: &str) -> Result<()> {
+    install_default_store(build_named_store(name)?)
+}
+
+/// Set the default store to one of the known stores in the specified configuration.
+///
+/// This sets the default store to the result of [build_named_store_with_modifiers], which see.
 pub fn use_named_store_with_modifiers(name: &str, modifiers: &HashMap<&str, &str>) -> Result<()> {
-    match name.to_lowercase().as_str() {
-        "android" => use_android_native_store(modifiers),
-        "keychain" => use_apple_keychain_store(modifiers),
-        "keyutils" => use_linux_keyutils_store(modifiers),
-        "protected" => use_apple_protected_store(modifiers),
-        "sample" => use_sample_store(modifiers),
-        "secret-service" | "zbus-secret-service" => use_zbus_secret_service_store(modifiers),
-        "dbus-secret-service" => use_dbus_secret_service_store(modifiers),
-        "sqlite" => use_sqlite_store(modifiers),
-        "windows" => use_windows_native_store(modifiers),
-        _ => {
-            let ok = NAMED_STORES.join(", ");
-            let err = Error::Invalid(name.to_string(), format!("must be one of: {ok}"));
-            Err(err)
+    install_default_store(build_named_store_with_modifiers(name, modifiers)?)
+}
+
+/// Create an entry for the given `service` and `user` in the given store.
+///
+/// Unlike [Entry::new](keyring_core::Entry::new), this doesn't use (or need)
+/// the default store, so it can be used to work with more than one store at a time.
+pub fn new_entry_in(store: &Arc<CredentialStore>, service: &str, user: &str) -> Result<Entry> {
+    store.build(service, user, None)
+}
+
+/// Create an entry for the given `service` and `user` in the given store,
+/// passing store-specific modifiers.
+///
+/// See [Entry::new_with_modifiers](keyring_core::Entry::new_with_modifiers).
+pub fn new_entry_with_modifiers_in(
+    store: &Arc<CredentialStore>,
+    service: &str,
+    user: &str,
+    modifiers: &HashMap<&str, &str>,
+) -> Result<Entry> {
+    store.build(service, user, Some(modifiers))
+}
+
+/// Search the given store for credentials.
+///
+/// See [Entry::search](keyring_core::Entry::search).
+pub fn search_in(store: &Arc<CredentialStore>, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+    store.search(spec)
+}
+
+/// A guard that restores a previous default store when it is dropped.
+///
+/// The guard captures the default store in effect when it is created
+/// (which may be no store at all) and, when it is dropped, makes that
+/// the default store again. Because drops happen during unwinding,
+/// the previous default store is restored even if a panic occurs
+/// while the guard is alive. This is useful in tests that need to
+/// swap in a different store for a block of code:
+///
+/// ```no_run
+/// # fn main() -> keyring_core::Result<()> {
+/// {
+///     let _guard = keyring::with_named_store("sample")?;
+///     // ... entries created here are in the sample store ...
+/// }
+/// // ... and the original default store is back in effect here.
+/// # Ok(())
+/// # }
+/// ```
+#[must_use = "the previous default store is restored as soon as the guard is dropped"]
+pub struct DefaultStoreGuard {
+    previous: Option<Arc<CredentialStore>>,
+}
+
+impl DefaultStoreGuard {
+    /// Capture the current default store, to be restored when the guard is dropped.
+    pub fn capture() -> Self {
+        DefaultStoreGuard {
+            previous: get_default_store(),
+        }
+    }
+
+    /// The default store that will be restored, if any.
+    pub fn previous(&self) -> Option<&Arc<CredentialStore>> {
+        self.previous.as_ref()
+    }
+}
+
+impl Drop for DefaultStoreGuard {
+    fn drop(&mut self) {
+        match self.previous.take() {
+            Some(store) => set_default_store(store),
+            None => {
+                unset_default_store();
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for DefaultStoreGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DefaultStoreGuard")
+            .field("previous", &self.previous)
+            .finish()
+    }
+}
+
+/// Temporarily set the default store to one of the known stores in its default configuration.
+///
+/// The returned guard restores the previous default store when dropped.
+/// If the store can't be set, the default store is left unchanged.
+///
+/// See [use_named_store] for the errors this can give.
+pub fn with_named_store(name: &str) -> Result<DefaultStoreGuard> {
+    let guard = DefaultStoreGuard::capture();
+    use_named_store(name)?;
+    Ok(guard)
+}
+
+/// Temporarily set the default store to one of the known stores in the specified configuration.
+///
+/// The returned guard restores the previous default store when dropped.
+/// If the store can't be set, the default store is left unchanged.
+///
+/// See [use_named_store_with_modifiers] for the errors this can give.
+pub fn with_named_store_with_modifiers(
+    name: &str,
+    modifiers: &HashMap<&str, &str>,
+) -> Result<DefaultStoreGuard> {
+    let guard = DefaultStoreGuard::capture();
+    use_named_store_with_modifiers(name, modifiers)?;
+    Ok(guard)
+}
+
+/// The outcome of choosing a default store from a list of candidates.
+///
+/// See [use_first_available_store].
+#[derive(Debug)]
+pub struct StoreChoice {
+    /// The name of the store that became the default.
+    pub name: String,
+    /// The candidates that were tried before it, with the errors they gave.
+    pub failures: Vec<(String, Error)>,
+}
+
+/// Set the default store to the first of the named stores that can be constructed.
+///
+/// The stores are tried in order, each in its default configuration (as by
+/// [use_named_store]), and the first one that succeeds becomes the default store.
+/// For example, on a headless Linux machine with no D-Bus session,
+/// `use_first_available_store(&["secret-service", "keyutils", "sample"])`
+/// will fall through the Secret Service and choose keyutils.
+///
+/// Gives an `Invalid` error if no names are given. If none of the stores can be
+/// constructed, gives a `NotSupportedByStore` error that describes why each failed.
+pub fn use_first_available_store(names: &[&str]) -> Result<StoreChoice> {
+    if names.is_empty() {
+        return Err(Error::Invalid(
+            "names".to_string(),
+            "must list at least one store".to_string(),
+        ));
+    }
+    let mut failures = Vec::new();
+    for name in names {
+        match use_named_store(name) {
+            Ok(()) => {
+                let name = canonical_name(name);
+                return Ok(StoreChoice { name, failures });
+            }
+            Err(err) => failures.push((name.to_string(), err)),
         }
     }
+    let reasons = failures
+        .iter()
+        .map(|(name, err)| format!("{name}: {err}"))
+        .collect::<Vec<_>>()
+        .join("; ");
+    Err(Error::NotSupportedByStore(format!(
+        "None of the stores could be used ({reasons})"
+    )))
+}
+
+/// The environment variable read by [use_store_from_env] to choose a store.
+///
+/// Its value is a store specification, as parsed by [parse_store_spec].
+pub const STORE_ENV_VAR: &str = "KEYRING_STORE";
+
+/// The environment variable read by [use_store_from_env] for store modifiers.
+///
+/// Its value is a list of `key=val` pairs, as parsed by [parse_modifiers].
+pub const STORE_MODIFIERS_ENV_VAR: &str = "KEYRING_STORE_MODIFIERS";
+
+/// Build a store from a store specification of the form `name:key1=val1,key2=val2`.
+///
+/// This is the syntax accepted by the `--module` flag of the keyring CLI.
+/// If there are no modifiers (and no `:`), the store is built by [build_named_store],
+/// otherwise by [build_named_store_with_modifiers].
+pub fn build_store_from_spec(spec: &str) -> Result<Arc<CredentialStore>> {
+    let (name, modifiers) = parse_store_spec(spec)?;
+    build_named_store_with_modifiers(&name, &internalize(Some(&modifiers)))
+}
+
+/// Set the default store from a store specification of the form `name:key1=val1,key2=val2`.
+///
+/// This is the syntax accepted by the `--module` flag of the keyring CLI.
+/// If there are no modifiers (and no `:`), the store is chosen by [use_named_store],
+/// otherwise by [use_named_store_with_modifiers].
+///
+/// Returns the (canonical) name of the chosen store.
+pub fn use_store_from_spec(spec: &str) -> Result<String> {
+    let (name, modifiers) = parse_store_spec(spec)?;
+    use_parsed_store_spec(&name, &modifiers)?;
+    Ok(name)
+}
+
+/// Set the default store as specified by the environment.
+///
+/// The store specification is read from the [STORE_ENV_VAR] (`KEYRING_STORE`)
+/// variable; see [use_store_from_spec] for its syntax. Modifiers can also be given
+/// in the [STORE_MODIFIERS_ENV_VAR] (`KEYRING_STORE_MODIFIERS`) variable; these
+/// are added to (and override) any given in the store specification.
+///
+/// Gives an `Invalid` error if `KEYRING_STORE` is not set or either variable
+/// can't be parsed. Otherwise, returns the (canonical) name of the chosen store.
+pub fn use_store_from_env() -> Result<String> {
+    let spec = std::env::var(STORE_ENV_VAR).map_err(|err| {
+        Error::Invalid(
+            STORE_ENV_VAR.to_string(),
+            format!("must name a credential store: {err}"),
+        )
+    })?;
+    let (name, mut modifiers) = parse_store_spec(&spec)?;
+    if let Ok(extra) = std::env::var(STORE_MODIFIERS_ENV_VAR) {
+        modifiers.extend(parse_modifiers(&extra)?);
+    }
+    use_parsed_store_spec(&name, &modifiers)?;
+    Ok(name)
+}
+
+fn use_parsed_store_spec(name: &str, modifiers: &HashMap<String, String>) -> Result<()> {
+    use_named_store_with_modifiers(name, &internalize(Some(modifiers)))
+}
+
+/// Options for [use_native_store_with_options].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NativeStoreOptions {
+    /// On Linux, use the Secret Service rather than the kernel keyutils store.
+    pub prefer_secret_service: bool,
+    /// Use the `systemd-creds` store (see [build_systemd_creds_store]) if the
+    /// process was started by systemd with credentials, that is, if the
+    /// [CREDENTIALS_DIRECTORY_ENV_VAR] (`CREDENTIALS_DIRECTORY`) variable is set.
+    pub prefer_systemd_creds: bool,
+}
+
+/// Set the default store to the platform's OS-provided credential store,
+/// as chosen by the given options.
+///
+/// This lets a program read its secrets with the same calls whether it runs
+/// on a desktop (where they are in the native store) or as a systemd service
+/// (where they are service credentials): see [NativeStoreOptions].
+/// Otherwise, this is the same as [use_native_store].
+pub fn use_native_store_with_options(options: NativeStoreOptions) -> Result<()> {
+    if options.prefer_systemd_creds
+        && std::env::var_os(CREDENTIALS_DIRECTORY_ENV_VAR).is_some_and(|dir| !dir.is_empty())
+    {
+        use_named_store("systemd-creds")
+    } else {
+        use_native_store(options.prefer_secret_service)
+    }
 }
 
 /// Set the default store to the platform's OS-provided credential store.
@@ -84,6 +1123,9 @@ pub fn use_named_store_with_modifiers(name: &str, modifiers: &HashMap<&str, &str
 /// On Linux (only), the kernel keyutils store is used unless
 /// `prefer_secret_service` is true, in which case the Secret Service
 /// store is used.
+///
+/// To use systemd service credentials when they are available, see
+/// [use_native_store_with_options].
 #[allow(unused_variables)]
 pub fn use_native_store(prefer_secret_service: bool) -> Result<()> {
     #[cfg(target_os = "android")]
@@ -112,25 +1154,143 @@ pub fn use_native_store(prefer_secret_service: bool) -> Result<()> {
     Ok(())
 }
 
-/// Set the default store to the `keyring-core::Sample` store.
+/// Build a store that keeps each credential in a file under a directory.
+///
+/// This is available on all platforms. See [DirStore] for the modifiers it takes.
+pub fn build_dir_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    Ok(DirStore::new_with_modifiers(config)?)
+}
+
+/// Set the default store to one that keeps each credential in a file under a directory.
+///
+/// This sets the default store to the result of [build_dir_store], which see.
+pub fn use_dir_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_dir_store(config)?;
+    record_selection(&store, "dir", config);
+    install_default_store(store)
+}
+
+/// The environment variable that systemd sets to the directory holding a service's credentials.
+pub const CREDENTIALS_DIRECTORY_ENV_VAR: &str = "CREDENTIALS_DIRECTORY";
+
+/// Build a read-only store of the credentials that systemd gives a service.
+///
+/// Services started with `LoadCredential=`, `SetCredentialEncrypted=`, and the like
+/// find their (decrypted) credentials in files in the directory named by the
+/// `CREDENTIALS_DIRECTORY` environment variable. This store reads those files,
+/// as a [DirStore] with the [service-only](DirLayout::ServiceOnly) layout: each
+/// file's whole name (such as `tls.key`) is a service, and the user is empty.
+/// So `Entry::new("db-password", "")` reads the credential loaded by
+/// `LoadCredential=db-password:...`. The store is read-only (see [ReadOnlyStore]).
+///
+/// The `directory` modifier gives the directory to use instead of `$CREDENTIALS_DIRECTORY`.
+///
+/// Fails with a `NotSupportedByStore` error on platforms other than Linux, and
+/// if no directory is given and `CREDENTIALS_DIRECTORY` isn't set.
+#[allow(unused_variables)]
+pub fn build_systemd_creds_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    #[cfg(target_os = "linux")]
+    {
+        let directory = match config.get("directory") {
+            Some(directory) => std::path::PathBuf::from(directory),
+            None => match std::env::var_os(CREDENTIALS_DIRECTORY_ENV_VAR) {
+                Some(directory) if !directory.is_empty() => directory.into(),
+                _ => {
+                    return Err(Error::NotSupportedByStore(format!(
+                        "The systemd-creds store is only available to services started with credentials (${CREDENTIALS_DIRECTORY_ENV_VAR} isn't set)"
+                    )));
+                }
+            },
+        };
+        Ok(ReadOnlyStore::new(DirStore::new(
+            directory,
+            DirLayout::ServiceOnly,
+        )))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Err(Error::NotSupportedByStore(
+            "The systemd-creds store is only available on Linux".to_string(),
+        ))
+    }
+}
+
+/// Set the default store to the store of the credentials that systemd gives a service.
+///
+/// This sets the default store to the result of [build_systemd_creds_store], which see.
+pub fn use_systemd_creds_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_systemd_creds_store(config)?;
+    record_selection(&store, "systemd-creds", config);
+    install_default_store(store)
+}
+
+/// Build a store that reads credentials from environment variables.
+///
+/// This is available on all platforms. See [EnvStore] for the modifiers it takes.
+///
+/// Fails with a `NotSupportedByStore` error unless the `env` feature is enabled.
+#[allow(unused_variables)]
+pub fn build_env_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    #[cfg(feature = "env")]
+    {
+        Ok(EnvStore::new_with_modifiers(config)?)
+    }
+    #[cfg(not(feature = "env"))]
+    {
+        Err(Error::NotSupportedByStore(
+            "The env store requires the env feature".to_string(),
+        ))
+    }
+}
+
+/// Set the default store to one that reads credentials from environment variables.
+///
+/// This sets the default store to the result of [build_env_store], which see.
+pub fn use_env_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_env_store(config)?;
+    record_selection(&store, "env", config);
+    install_default_store(store)
+}
+
+/// Build the `keyring-core::Sample` store.
 ///
 /// This is available on all platforms.
-pub fn use_sample_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_sample_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     use keyring_core::sample::Store;
-    set_default_store(Store::new_with_configuration(config)?);
-    Ok(())
+    Ok(Store::new_with_configuration(config)?)
 }
 
-/// Use the macOS Keychain Services store.
+/// Build the sample store when it's chosen by name.
+///
+/// Unlike [build_sample_store], this persists the store (as its declared
+/// `persist` default says) unless it's given `persist` or `backing-file`.
+fn build_named_sample_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    if config.contains_key("persist") || config.contains_key("backing-file") {
+        return build_sample_store(config);
+    }
+    let mut config = config.clone();
+    config.insert("persist", "true");
+    build_sample_store(&config)
+}
+
+/// Set the default store to the `keyring-core::Sample` store.
+///
+/// This sets the default store to the result of [build_sample_store], which see.
+pub fn use_sample_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_sample_store(config)?;
+    record_selection(&store, "sample", config);
+    install_default_store(store)
+}
+
+/// Build the macOS Keychain Services store.
 ///
 /// Fails with a `NotSupportedByStore` error on other platforms.
 #[allow(unused_variables)]
-pub fn use_apple_keychain_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_apple_keychain_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(target_os = "macos")]
     {
         use apple_native_keyring_store::keychain::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(target_os = "macos"))]
     {
@@ -140,7 +1300,16 @@ pub fn use_apple_keychain_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
-/// Use the iOS/macOS Protected Data store.
+/// Use the macOS Keychain Services store.
+///
+/// This sets the default store to the result of [build_apple_keychain_store], which see.
+pub fn use_apple_keychain_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_apple_keychain_store(config)?;
+    record_selection(&store, "keychain", config);
+    install_default_store(store)
+}
+
+/// Build the iOS/macOS Protected Data store.
 ///
 /// NOTE: macOS apps without a provisioning profile
 /// cannot use the protected store. Because an app cannot
@@ -156,12 +1325,11 @@ pub fn use_apple_keychain_store(config: &HashMap<&str, &str>) -> Result<()> {
 ///
 /// Fails with a `NotSupportedByStore` error on other platforms.
 #[allow(unused_variables)]
-pub fn use_apple_protected_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_apple_protected_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(target_os = "macos")]
     if std::env::var("APP_SANDBOX_CONTAINER_ID").is_ok() {
         use apple_native_keyring_store::protected::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     } else {
         Err(Error::NotSupportedByStore(
             "The macOS Protected Data store requires a provisioning profile".to_string(),
@@ -170,8 +1338,7 @@ pub fn use_apple_protected_store(config: &HashMap<&str, &str>) -> Result<()> {
     #[cfg(target_os = "ios")]
     {
         use apple_native_keyring_store::protected::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(any(target_os = "macos", target_os = "ios")))]
     {
@@ -181,16 +1348,24 @@ pub fn use_apple_protected_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
-/// Use the Linux Keyutils store.
+/// Use the iOS/macOS Protected Data store.
+///
+/// This sets the default store to the result of [build_apple_protected_store], which see.
+pub fn use_apple_protected_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_apple_protected_store(config)?;
+    record_selection(&store, "protected", config);
+    install_default_store(store)
+}
+
+/// Build the Linux Keyutils store.
 ///
 /// Fails with a `NotSupportedByStore` error on other platforms.
 #[allow(unused_variables)]
-pub fn use_linux_keyutils_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_linux_keyutils_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(target_os = "linux")]
     {
         use linux_keyutils_keyring_store::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(target_os = "linux"))]
     {
@@ -200,7 +1375,16 @@ pub fn use_linux_keyutils_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
-/// Use the dbus-based Secret Service store via `libdbus`.
+/// Use the Linux Keyutils store.
+///
+/// This sets the default store to the result of [build_linux_keyutils_store], which see.
+pub fn use_linux_keyutils_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_linux_keyutils_store(config)?;
+    record_selection(&store, "keyutils", config);
+    install_default_store(store)
+}
+
+/// Build the dbus-based Secret Service store via `libdbus`.
 ///
 /// Fails with a `NotSupportedByStore` error except on Linux and *nix platforms.
 ///
@@ -210,15 +1394,16 @@ pub fn use_linux_keyutils_store(config: &HashMap<&str, &str>) -> Result<()> {
 /// to use the Secret Service on other platforms, you should directly link with the
 /// [keyring-core] crate and your chosen Secret Service credential store module.
 #[allow(unused_variables)]
-pub fn use_dbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_dbus_secret_service_store(
+    config: &HashMap<&str, &str>,
+) -> Result<Arc<CredentialStore>> {
     #[cfg(all(
         unix,
         not(any(target_os = "macos", target_os = "ios", target_os = "android"))
     ))]
     {
         use dbus_secret_service_keyring_store::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(all(
         unix,
@@ -231,7 +1416,16 @@ pub fn use_dbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()>
     }
 }
 
-/// Use the dbus-based Secret Service store via `zbus`.
+/// Use the dbus-based Secret Service store via `libdbus`.
+///
+/// This sets the default store to the result of [build_dbus_secret_service_store], which see.
+pub fn use_dbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_dbus_secret_service_store(config)?;
+    record_selection(&store, "dbus-secret-service", config);
+    install_default_store(store)
+}
+
+/// Build the dbus-based Secret Service store via `zbus`.
 ///
 /// Fails with a `NotSupportedByStore` error except on Linux and *nix platforms.
 ///
@@ -241,15 +1435,16 @@ pub fn use_dbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()>
 /// to use the Secret Service on other platforms, you should directly link with the
 /// [keyring-core] crate and your chosen Secret Service credential store module.
 #[allow(unused_variables)]
-pub fn use_zbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_zbus_secret_service_store(
+    config: &HashMap<&str, &str>,
+) -> Result<Arc<CredentialStore>> {
     #[cfg(all(
         unix,
         not(any(target_os = "macos", target_os = "ios", target_os = "android"))
     ))]
     {
         use zbus_secret_service_keyring_store::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(all(
         unix,
@@ -262,16 +1457,24 @@ pub fn use_zbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()>
     }
 }
 
-/// Use the Windows Credential store.
+/// Use the dbus-based Secret Service store via `zbus`.
+///
+/// This sets the default store to the result of [build_zbus_secret_service_store], which see.
+pub fn use_zbus_secret_service_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_zbus_secret_service_store(config)?;
+    record_selection(&store, "secret-service", config);
+    install_default_store(store)
+}
+
+/// Build the Windows Credential store.
 ///
 /// Fails with a `NotSupportedByStore` error on other platforms.
 #[allow(unused_variables)]
-pub fn use_windows_native_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_windows_native_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(target_os = "windows")]
     {
         use windows_native_keyring_store::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(target_os = "windows"))]
     {
@@ -281,18 +1484,26 @@ pub fn use_windows_native_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
-/// Use the Android Shared Preferences store.
+/// Use the Windows Credential store.
+///
+/// This sets the default store to the result of [build_windows_native_store], which see.
+pub fn use_windows_native_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_windows_native_store(config)?;
+    record_selection(&store, "windows", config);
+    install_default_store(store)
+}
+
+/// Build the Android Shared Preferences store.
 ///
 /// Shared Preference data is encrypted using the Android keystore.
 ///
 /// Fails with a `NotSupportedByStore` error on other platforms.
 #[allow(unused_variables)]
-pub fn use_android_native_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_android_native_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(target_os = "android")]
     {
         use android_native_keyring_store::Store;
-        set_default_store(Store::new_with_configuration(config)?);
-        Ok(())
+        Ok(Store::new_with_configuration(config)?)
     }
     #[cfg(not(target_os = "android"))]
     {
@@ -302,14 +1513,68 @@ pub fn use_android_native_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
-/// Use a cross-platform encrypted sqlite (Turso) database.
+/// Use the Android Shared Preferences store.
+///
+/// This sets the default store to the result of [build_android_native_store], which see.
+pub fn use_android_native_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_android_native_store(config)?;
+    record_selection(&store, "android", config);
+    install_default_store(store)
+}
+
+/// Build a store that runs a helper program for each operation.
+///
+/// This is available on all platforms. See [ExecStore] for the modifiers it takes
+/// and the protocol its helper must speak.
+pub fn build_exec_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    Ok(ExecStore::new_with_modifiers(config)?)
+}
+
+/// Use a helper program as the credential store.
+///
+/// This sets the default store to the result of [build_exec_store], which see.
+pub fn use_exec_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_exec_store(config)?;
+    record_selection(&store, "exec", config);
+    install_default_store(store)
+}
+
+/// Build a store that keeps credentials in a KeePass (KDBX4) database file.
+///
+/// The `path` modifier gives the database file, and the `password` and/or
+/// `keyfile` modifiers give its key: see `KdbxStore` for details.
+///
+/// Fails with a `NotSupportedByStore` error unless the `kdbx` feature is enabled.
 #[allow(unused_variables)]
-pub fn use_sqlite_store(config: &HashMap<&str, &str>) -> Result<()> {
+pub fn build_kdbx_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
+    #[cfg(feature = "kdbx")]
+    {
+        Ok(KdbxStore::new_with_modifiers(config)?)
+    }
+    #[cfg(not(feature = "kdbx"))]
+    {
+        Err(Error::NotSupportedByStore(
+            "The kdbx store requires the kdbx feature".to_string(),
+        ))
+    }
+}
+
+/// Use a KeePass (KDBX4) database file.
+///
+/// This sets the default store to the result of [build_kdbx_store], which see.
+pub fn use_kdbx_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_kdbx_store(config)?;
+    record_selection(&store, "kdbx", config);
+    install_default_store(store)
+}
+
+/// Build a cross-platform encrypted sqlite (Turso) database.
+#[allow(unused_variables)]
+pub fn build_sqlite_store(config: &HashMap<&str, &str>) -> Result<Arc<CredentialStore>> {
     #[cfg(not(any(target_os = "ios", target_os = "android")))]
     {
         use db_keystore::DbKeyStore;
-        set_default_store(DbKeyStore::new_with_modifiers(config)?);
-        Ok(())
+        Ok(DbKeyStore::new_with_modifiers(config)?)
     }
     #[cfg(any(target_os = "ios", target_os = "android"))]
     {
@@ -319,12 +1584,80 @@ pub fn use_sqlite_store(config: &HashMap<&str, &str>) -> Result<()> {
     }
 }
 
+/// Use a cross-platform encrypted sqlite (Turso) database.
+///
+/// This sets the default store to the result of [build_sqlite_store], which see.
+pub fn use_sqlite_store(config: &HashMap<&str, &str>) -> Result<()> {
+    let store = build_sqlite_store(config)?;
+    record_selection(&store, "sqlite", config);
+    install_default_store(store)
+}
+
 /// Release the current default store.
 pub fn release_store() {
     unset_default_store();
 }
 
+/// A description of a credential store.
+///
+/// With the `serde` feature, this is serializable, so it can be printed
+/// as JSON or saved for later comparison.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StoreInfo {
+    /// The vendor of the store (see [CredentialStoreApi::vendor](keyring_core::api::CredentialStoreApi::vendor)).
+    pub vendor: String,
+    /// The instance id of the store (see [CredentialStoreApi::id](keyring_core::api::CredentialStoreApi::id)).
+    pub id: String,
+    /// The lifetime of credentials in the store, e.g., `UntilDelete`.
+    pub persistence: String,
+    /// The name the store was chosen under, if it was chosen by name
+    /// or by one of the `use_*_store` functions.
+    pub name: Option<String>,
+    /// The modifiers the store was built with, if it has a name.
+    ///
+    /// The values of modifiers that are secrets, such as the `hexkey` of
+    /// an encrypted sqlite store or the `password` of a KeePass database,
+    /// are given as `<redacted>`.
+    pub modifiers: HashMap<String, String>,
+}
+
+impl StoreInfo {
+    /// Describe the given store.
+    pub fn for_store(store: &Arc<CredentialStore>) -> Self {
+        let (name, modifiers) = match selection_of(store) {
+            Some((name, modifiers)) => (Some(name), modifiers),
+            None => (None, HashMap::new()),
+        };
+        StoreInfo {
+            vendor: store.vendor(),
+            id: store.id(),
+            persistence: persistence_name(store.persistence()).to_string(),
+            name,
+            modifiers,
+        }
+    }
+}
+
+fn persistence_name(persistence: CredentialPersistence) -> &'static str {
+    match persistence {
+        CredentialPersistence::EntryOnly => "EntryOnly",
+        CredentialPersistence::ProcessOnly => "ProcessOnly",
+        CredentialPersistence::UntilLogout => "UntilLogout",
+        CredentialPersistence::UntilReboot => "UntilReboot",
+        CredentialPersistence::UntilDelete => "UntilDelete",
+        _ => "Unspecified",
+    }
+}
+
+/// Returns a description of the current default store, if there is one.
+pub fn default_store_info() -> Option<StoreInfo> {
+    get_default_store().map(|store| StoreInfo::for_store(&store))
+}
+
 /// Returns a debug description of the current default store.
+///
+/// See [default_store_info] for a structured description.
 pub fn store_info() -> String {
     if let Some(store) = get_default_store() {
         format!("{store:?}")
@@ -346,3 +1679,503 @@ pub fn internalize(config: Option<&HashMap<String, String>>) -> HashMap<&str, &s
         HashMap::new()
     }
 }
+
+/// Parse a store specification of the form `name:key1=val1,key2=val2`.
+///
+/// The modifiers (and the `:` that introduces them) are optional.
+/// Returns the canonical store name and the parsed modifiers.
+///
+/// Gives an `Invalid` error if the name is empty or the modifiers can't be parsed.
+pub fn parse_store_spec(spec: &str) -> Result<(String, HashMap<String, String>)> {
+    let (name, rest) = spec.split_once(':').unwrap_or((spec, ""));
+    if name.is_empty() {
+        return Err(Error::Invalid(
+            spec.to_string(),
+            "must start with a store name".to_string(),
+        ));
+    }
+    Ok((canonical_name(name), parse_modifiers(rest)?))
+}
+
+/// Parse a comma-separated list of `key=val` pairs.
+///
+/// An empty input gives an empty map. Gives an `Invalid` error if any part
+/// of the input isn't a `key=val` pair with a non-empty key.
+pub fn parse_modifiers(input: &str) -> Result<HashMap<String, String>> {
+    let mut modifiers = HashMap::new();
+    if input.is_empty() {
+        return Ok(modifiers);
+    }
+    for part in input.split(',') {
+        match part.split_once('=') {
+            Some((key, val)) if !key.is_empty() && !val.contains('=') => {
+                modifiers.insert(key.to_string(), val.to_string());
+            }
+            _ => {
+                return Err(Error::Invalid(
+                    part.to_string(),
+                    "is not a key=val pair".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(modifiers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that check which store is the default must hold this lock.
+    pub(super) static DEFAULT_STORE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn test_builtin_stores_registered() {
+        let names: Vec<String> = registered_stores().into_iter().map(|(n, _)| n).collect();
+        for name in NAMED_STORES {
+            assert!(
+                names.contains(&name.to_string()),
+                "{name} is not registered"
+            );
+        }
+    }
+
+    #[test]
+    fn test_register_and_unregister() {
+        register_store("test-registry", "a test store", |_| {
+            build_sample_store(&HashMap::new())
+        })
+        .unwrap();
+        match register_store("Test-Registry", "a duplicate", |_| {
+            build_sample_store(&HashMap::new())
+        }) {
+            Err(Error::Invalid(name, _)) => assert_eq!(name, "test-registry"),
+            other => panic!("Duplicate registration didn't fail properly: {other:?}"),
+        }
+        assert!(
+            registered_stores()
+                .iter()
+                .any(|(n, d)| n == "test-registry" && d == "a test store")
+        );
+        build_named_store_with_modifiers("test-registry", &HashMap::new()).unwrap();
+        assert!(unregister_store("test-registry"));
+        assert!(!unregister_store("test-registry"));
+        match build_named_store_with_modifiers("test-registry", &HashMap::new()) {
+            Err(Error::Invalid(name, _)) => assert_eq!(name, "test-registry"),
+            other => panic!("Unregistered store was found: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_store_modifiers() {
+        let sample = store_modifiers("Sample").unwrap().unwrap();
+        assert!(
+            sample
+                .iter()
+                .any(|m| m.key == "persist" && m.kind == ModifierKind::Bool)
+        );
+        assert!(store_modifiers("no-such-store").is_err());
+        match build_named_store_with_modifiers("sample", &HashMap::from([("persit", "true")])) {
+            Err(Error::Invalid(key, _)) => assert_eq!(key, "persit"),
+            other => panic!("Unknown modifier was accepted: {other:?}"),
+        }
+        let primary = HashMap::from([
+            ("primary", "sample"),
+            ("secondary", "sample"),
+            ("primary.persist", "false"),
+        ]);
+        validate_modifiers("layered", &primary).unwrap();
+        let specs = vec![ModifierSpec {
+            key: "size".to_string(),
+            kind: ModifierKind::Integer,
+            default: None,
+            description: "a size".to_string(),
+        }];
+        register_store_with_modifiers("test-modifiers", "a test store", specs, |_| {
+            build_sample_store(&HashMap::new())
+        })
+        .unwrap();
+        validate_modifiers("test-modifiers", &HashMap::from([("size", "3")])).unwrap();
+        assert!(validate_modifiers("test-modifiers", &HashMap::from([("size", "big")])).is_err());
+        assert!(validate_modifiers("test-modifiers", &HashMap::from([("color", "red")])).is_err());
+        assert!(unregister_store("test-modifiers"));
+        register_store("test-no-modifiers", "a test store", |_| {
+            build_sample_store(&HashMap::new())
+        })
+        .unwrap();
+        assert_eq!(store_modifiers("test-no-modifiers").unwrap(), None);
+        validate_modifiers("test-no-modifiers", &HashMap::from([("color", "red")])).unwrap();
+        assert!(unregister_store("test-no-modifiers"));
+    }
+
+    #[test]
+    fn test_wrapper_modifiers() {
+        let cached = HashMap::from([("persist", "false"), ("cache_ttl_secs", "30")]);
+        let store = build_named_store_with_modifiers("sample", &cached).unwrap();
+        assert!(store.as_any().is::<CachingStore>());
+        let uncached = HashMap::from([("persist", "false"), ("cache-ttl-secs", "0")]);
+        let store = build_named_store_with_modifiers("sample", &uncached).unwrap();
+        assert!(!store.as_any().is::<CachingStore>());
+        let bad = HashMap::from([("cache-ttl-secs", "soon")]);
+        assert!(build_named_store_with_modifiers("sample", &bad).is_err());
+        let retrying = HashMap::from([("persist", "false"), ("retry-attempts", "5")]);
+        let store = build_named_store_with_modifiers("sample", &retrying).unwrap();
+        let retry = store.as_any().downcast_ref::<RetryingStore>().unwrap();
+        assert_eq!(retry.policy().max_attempts, 5);
+        let bad = HashMap::from([("retry-attempts", "5"), ("retry-on", "Sometimes")]);
+        assert!(build_named_store_with_modifiers("sample", &bad).is_err());
+        let timed = HashMap::from([("persist", "false"), ("timeout-ms", "1500")]);
+        let store = build_named_store_with_modifiers("sample", &timed).unwrap();
+        let timeout = store.as_any().downcast_ref::<TimeoutStore>().unwrap();
+        assert_eq!(timeout.timeout(), Duration::from_millis(1500));
+        let namespaced = HashMap::from([("persist", "false"), ("namespace", "staging")]);
+        let store = build_named_store_with_modifiers("sample", &namespaced).unwrap();
+        let namespace = store.as_any().downcast_ref::<NamespaceStore>().unwrap();
+        assert_eq!(namespace.prefix(), "staging:");
+        let specs = store_modifiers("secret-service").unwrap().unwrap();
+        assert!(specs.iter().any(|m| m.key == "cache-ttl-secs"));
+    }
+
+    #[test]
+    fn test_operation_timeout() {
+        // The global timeout isn't set here, because other tests build named
+        // stores concurrently and would be wrapped by it.
+        let global = Some(Duration::from_secs(30));
+        assert_eq!(store_timeout(&HashMap::new(), global).unwrap(), global);
+        let untimed = HashMap::from([("timeout_ms", "0")]);
+        assert_eq!(store_timeout(&untimed, global).unwrap(), None);
+        let timed = HashMap::from([("timeout-ms", "1500")]);
+        assert_eq!(
+            store_timeout(&timed, None).unwrap(),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(store_timeout(&HashMap::new(), None).unwrap(), None);
+        let store = HashMap::from([("persist", "false"), ("timeout-ms", "1000")]);
+        let store = build_named_store_with_modifiers("sample", &store).unwrap();
+        assert!(store.as_any().is::<TimeoutStore>());
+        assert_eq!(operation_timeout(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_systemd_creds_store() {
+        let dir = std::env::temp_dir().join(format!("keyring-creds-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("db-password"), "from systemd").unwrap();
+        std::fs::write(dir.join("tls.key"), "key").unwrap();
+        let config = HashMap::from([("directory", dir.to_str().unwrap())]);
+        let store = build_named_store_with_modifiers("systemd-creds", &config).unwrap();
+        let entry = new_entry_in(&store, "db-password", "").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "from systemd");
+        assert!(matches!(
+            entry.set_password("changed"),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let tls = new_entry_in(&store, "tls.key", "").unwrap();
+        assert_eq!(tls.get_password().unwrap(), "key");
+        let found = store.search(&HashMap::from([("user", "")])).unwrap();
+        assert_eq!(found.len(), 2);
+        let spec = HashMap::from([("service", "tls.key")]);
+        assert_eq!(store.search(&spec).unwrap().len(), 1);
+        assert!(new_entry_in(&store, "tls", "key").is_err());
+        let bad = HashMap::from([("directory", "/run"), ("dir", "/run")]);
+        assert!(build_named_store_with_modifiers("systemd-creds", &bad).is_err());
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        use_systemd_creds_store(&config).unwrap();
+        assert_eq!(
+            default_store_info().unwrap().name.as_deref(),
+            Some("systemd-creds")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_make_default_store_read_only() {
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        release_store();
+        assert!(matches!(
+            make_default_store_read_only(),
+            Err(Error::NoDefaultStore)
+        ));
+        use_named_store_with_modifiers("sample", &HashMap::from([("persist", "false")])).unwrap();
+        make_default_store_read_only().unwrap();
+        make_default_store_read_only().unwrap();
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("sample"));
+        assert_eq!(
+            info.modifiers.get("read-only").map(String::as_str),
+            Some("true")
+        );
+        let entry = Entry::new("svc", "usr").unwrap();
+        assert!(matches!(
+            entry.set_password("changed"),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let read_only = HashMap::from([("persist", "false"), ("read_only", "true")]);
+        let store = build_named_store_with_modifiers("sample", &read_only).unwrap();
+        assert!(store.as_any().is::<ReadOnlyStore>());
+    }
+
+    #[test]
+    #[cfg(feature = "audit")]
+    fn test_enable_audit_log() {
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        let path = std::env::temp_dir().join(format!("keyring-audit-{}.jsonl", fastrand::u64(..)));
+        let no_persist = HashMap::from([("persist", "false")]);
+        use_named_store_with_modifiers("sample", &no_persist).unwrap();
+        enable_audit_log(&path).unwrap();
+        let store = get_default_store().unwrap();
+        assert!(store.as_any().is::<AuditingStore>());
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("sample"));
+        assert!(info.modifiers.contains_key("audit-log"));
+        use_named_store_with_modifiers("sample", &no_persist).unwrap();
+        let entry = Entry::new("svc", "usr").unwrap();
+        assert!(entry.get_password().is_err());
+        disable_audit_log();
+        use_named_store_with_modifiers("sample", &no_persist).unwrap();
+        let store = get_default_store().unwrap();
+        assert!(!store.as_any().is::<AuditingStore>());
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains(r#""operation":"get_secret""#));
+    }
+
+    #[test]
+    fn test_register_bad_names() {
+        for name in ["", "bad:name", "bad,name"] {
+            assert!(matches!(
+                register_store(name, "bad", |_| build_sample_store(&HashMap::new())),
+                Err(Error::Invalid(_, _))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_available_stores() {
+        let report = available_stores();
+        for name in NAMED_STORES {
+            assert!(
+                report.iter().any(|r| r.name == name),
+                "{name} wasn't reported"
+            );
+        }
+        let sample = report.iter().find(|r| r.name == "sample").unwrap();
+        assert!(sample.compiled_in && sample.platform_supported && sample.is_available());
+        for name in ["exec", "layered", "mirror"] {
+            let r = report.iter().find(|r| r.name == name).unwrap();
+            assert!(r.platform_supported && r.needs_configuration && !r.is_available());
+            assert_eq!(r.error, None);
+        }
+        for r in report.iter().filter(|r| !r.compiled_in) {
+            assert!(!r.platform_supported && !r.is_available());
+        }
+    }
+
+    #[test]
+    fn test_use_first_available_store() {
+        let _lock = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        register_store("test-fallback-fail", "always fails", |_| {
+            Err(Error::NotSupportedByStore("test".to_string()))
+        })
+        .unwrap();
+        register_store("test-fallback-ok", "always succeeds", |_| {
+            build_sample_store(&HashMap::new())
+        })
+        .unwrap();
+        let choice =
+            use_first_available_store(&["no-such-store", "test-fallback-fail", "test-fallback-ok"])
+                .unwrap();
+        assert_eq!(choice.name, "test-fallback-ok");
+        assert_eq!(choice.failures.len(), 2);
+        assert!(matches!(choice.failures[0].1, Error::Invalid(_, _)));
+        assert!(matches!(
+            choice.failures[1].1,
+            Error::NotSupportedByStore(_)
+        ));
+        match use_first_available_store(&["test-fallback-fail"]) {
+            Err(Error::NotSupportedByStore(msg)) => assert!(msg.contains("test-fallback-fail")),
+            other => panic!("Failed fallback gave the wrong result: {other:?}"),
+        }
+        assert!(use_first_available_store(&[]).is_err());
+        unregister_store("test-fallback-fail");
+        unregister_store("test-fallback-ok");
+    }
+
+    #[test]
+    fn test_parse_store_spec() {
+        let (name, mods) = parse_store_spec("Sample").unwrap();
+        assert_eq!(name, "sample");
+        assert!(mods.is_empty());
+        let (name, mods) = parse_store_spec("zbus-secret-service:").unwrap();
+        assert_eq!(name, "secret-service");
+        assert!(mods.is_empty());
+        let (name, mods) = parse_store_spec("sample:persist=true,backing-file=a.ron").unwrap();
+        assert_eq!(name, "sample");
+        assert_eq!(mods.len(), 2);
+        assert_eq!(mods.get("persist"), Some(&"true".to_string()));
+        assert_eq!(mods.get("backing-file"), Some(&"a.ron".to_string()));
+        for bad in [
+            ":persist=true",
+            "sample:persist",
+            "sample:=true",
+            "sample:a=b=c",
+        ] {
+            assert!(
+                matches!(parse_store_spec(bad), Err(Error::Invalid(_, _))),
+                "{bad} was parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn test_use_store_from_env() {
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            std::env::remove_var(STORE_ENV_VAR);
+            std::env::remove_var(STORE_MODIFIERS_ENV_VAR);
+        }
+        match use_store_from_env() {
+            Err(Error::Invalid(key, _)) => assert_eq!(key, STORE_ENV_VAR),
+            other => panic!("An unset {STORE_ENV_VAR} gave: {other:?}"),
+        }
+        // SAFETY: as above
+        unsafe {
+            std::env::set_var(STORE_ENV_VAR, "Sample:persist=true,namespace=from-env");
+            std::env::set_var(STORE_MODIFIERS_ENV_VAR, "persist=false");
+        }
+        let name = use_store_from_env();
+        // SAFETY: as above
+        unsafe {
+            std::env::remove_var(STORE_ENV_VAR);
+            std::env::remove_var(STORE_MODIFIERS_ENV_VAR);
+        }
+        assert_eq!(name.unwrap(), "sample");
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("sample"));
+        assert_eq!(info.modifiers["persist"], "false");
+        assert_eq!(info.modifiers["namespace"], "from-env");
+        assert_eq!(info.persistence, "ProcessOnly");
+    }
+
+    #[test]
+    fn test_default_store_info() {
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        use_named_store_with_modifiers("Sample", &HashMap::from([("persist", "false")])).unwrap();
+        let info = default_store_info().unwrap();
+        assert!(info.vendor.starts_with("Sample store"));
+        assert_eq!(info.persistence, "ProcessOnly");
+        assert_eq!(info.name, Some("sample".to_string()));
+        assert_eq!(info.modifiers.get("persist"), Some(&"false".to_string()));
+        use_sample_store(&HashMap::new()).unwrap();
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name, Some("sample".to_string()));
+        assert!(info.modifiers.is_empty());
+        release_store();
+        assert_eq!(default_store_info(), None);
+    }
+
+    #[test]
+    fn test_secret_modifiers_redacted() {
+        let store = build_sample_store(&HashMap::new()).unwrap();
+        let modifiers = HashMap::from([
+            ("path", "keys.db"),
+            ("hexkey", "00ff"),
+            ("encryption_hexkey", "00ff"),
+            ("primary.encryption-hexkey", "00ff"),
+            ("secondary.password", "master"),
+        ]);
+        record_selection(&store, "sqlite", &modifiers);
+        let info = StoreInfo::for_store(&store);
+        assert_eq!(info.modifiers.len(), 5);
+        assert_eq!(info.modifiers["path"], "keys.db");
+        for (key, value) in &info.modifiers {
+            assert!(
+                key == "path" || value == REDACTED_VALUE,
+                "{key} was recorded"
+            );
+        }
+    }
+
+    #[test]
+    fn test_use_store_functions() {
+        let _guard = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        #[cfg(feature = "env")]
+        {
+            let env = HashMap::from([("template", "KEYRING_TEST_{SERVICE}")]);
+            use_env_store(&env).unwrap();
+            let info = default_store_info().unwrap();
+            assert_eq!(info.name.as_deref(), Some("env"));
+            assert_eq!(info.modifiers["template"], "KEYRING_TEST_{SERVICE}");
+        }
+        let root = std::env::temp_dir().join(format!("keyring-use-dir-{}", fastrand::u64(..)));
+        let dir = HashMap::from([("root", root.to_str().unwrap()), ("layout", "nested")]);
+        use_dir_store(&dir).unwrap();
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("dir"));
+        assert_eq!(info.modifiers["layout"], "nested");
+        use_exec_store(&HashMap::from([("command", "keyring-helper")])).unwrap();
+        let info = default_store_info().unwrap();
+        assert_eq!(info.name.as_deref(), Some("exec"));
+        assert_eq!(info.modifiers["command"], "keyring-helper");
+    }
+
+    #[test]
+    fn test_default_store_guard() {
+        let _lock = DEFAULT_STORE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mods = HashMap::from([("persist", "false")]);
+        use_named_store_with_modifiers("sample", &mods).unwrap();
+        let original = get_default_store().unwrap();
+        {
+            let guard = with_named_store_with_modifiers("sample", &mods).unwrap();
+            assert!(Arc::ptr_eq(guard.previous().unwrap(), &original));
+            assert!(!Arc::ptr_eq(&get_default_store().unwrap(), &original));
+        }
+        assert!(Arc::ptr_eq(&get_default_store().unwrap(), &original));
+        let result = std::panic::catch_unwind(|| {
+            let _guard = with_named_store_with_modifiers("sample", &mods).unwrap();
+            panic!("unwinding with a guard");
+        });
+        assert!(result.is_err());
+        assert!(Arc::ptr_eq(&get_default_store().unwrap(), &original));
+        assert!(with_named_store("no-such-store").is_err());
+        assert!(Arc::ptr_eq(&get_default_store().unwrap(), &original));
+        assert_eq!(
+            default_store_info().unwrap().name,
+            Some("sample".to_string())
+        );
+        release_store();
+        {
+            let _guard = with_named_store_with_modifiers("sample", &mods).unwrap();
+        }
+        assert!(get_default_store().is_none());
+    }
+
+    #[test]
+    fn test_store_handles() {
+        let mods = HashMap::from([("persist", "false")]);
+        let store1 = build_named_store_with_modifiers("sample", &mods).unwrap();
+        let store2 = build_named_store_with_modifiers("sample", &mods).unwrap();
+        let entry1 = new_entry_in(&store1, "handle-service", "handle-user").unwrap();
+        let entry2 = new_entry_in(&store2, "handle-service", "handle-user").unwrap();
+        entry1.set_password("in store 1").unwrap();
+        assert!(matches!(entry2.get_password(), Err(Error::NoEntry)));
+        entry2.set_password("in store 2").unwrap();
+        assert_eq!(entry1.get_password().unwrap(), "in store 1");
+        let spec = HashMap::from([("service", "handle-service")]);
+        assert_eq!(search_in(&store1, &spec).unwrap().len(), 1);
+        assert_eq!(
+            StoreInfo::for_store(&store2).name,
+            Some("sample".to_string())
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! Encrypted archives of store contents.
+//!
+//! An archive holds the service, user, attributes, and secret of a set of
+//! credentials, along with the vendor and id of the store they came from. It can
+//! be restored into any store, so it serves both as a backup and as a way to carry
+//! credentials from one machine to another.
+//!
+//! The archive file is a short header (format marker, version, key-derivation salt,
+//! and nonce) followed by the RON-encoded contents, encrypted with XChaCha20-Poly1305
+//! under a key derived from a passphrase with Argon2id (whose cost parameters are
+//! fixed by the format version). The header is authenticated along with the
+//! contents, so any change to the file is detected when it's opened.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use keyring_core::{CredentialStore, Error, Result};
+
+use super::build_store_from_spec;
+use super::dir::write_private;
+use super::migrate::copy_credential;
+use super::{ConflictPolicy, MigratedEntry, MigrationOutcome, MigrationReport};
+
+const MAGIC: &[u8; 8] = b"KRARCHV\0";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+/// One credential in an archive.
+///
+/// The secret is wiped from memory when the credential is dropped.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedCredential {
+    pub service: String,
+    pub user: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    pub secret: Vec<u8>,
+}
+
+impl std::fmt::Debug for ArchivedCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArchivedCredential")
+            .field("service", &self.service)
+            .field("user", &self.user)
+            .field("attributes", &self.attributes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ArchivedCredential {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+/// The contents of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Archive {
+    /// The vendor of the store the credentials were exported from.
+    pub vendor: String,
+    /// The id of the store the credentials were exported from.
+    pub id: String,
+    pub credentials: Vec<ArchivedCredential>,
+}
+
+/// Options for restoring an archive.
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    /// Report what would be done without changing the target store.
+    pub dry_run: bool,
+    /// What to do with credentials that already exist in the target store.
+    pub on_conflict: ConflictPolicy,
+}
+
+impl Archive {
+    /// Collect the credentials in a store that match a search query.
+    ///
+    /// Unlike a migration, an export is all or nothing: if any matching
+    /// credential can't be read, the error is returned.
+    pub fn from_store(store: &Arc<CredentialStore>, query: &HashMap<&str, &str>) -> Result<Self> {
+        let mut credentials = Vec::new();
+        for entry in store.search(query)? {
+            let Some((service, user)) = entry.get_specifiers() else {
+                return Err(Error::NotSupportedByStore(
+                    "Credentials without a service and user can't be exported".to_string(),
+                ));
+            };
+            credentials.push(ArchivedCredential {
+                service,
+                user,
+                attributes: entry.get_attributes()?,
+                secret: entry.get_secret()?,
+            });
+        }
+        Ok(Archive {
+            vendor: store.vendor(),
+            id: store.id(),
+            credentials,
+        })
+    }
+
+    /// Encrypt the archive with a key derived from the given passphrase.
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(MAGIC);
+        header.push(VERSION);
+        let mut random = [0u8; SALT_LEN + NONCE_LEN];
+        getrandom::fill(&mut random).map_err(|err| Error::PlatformFailure(Box::new(err)))?;
+        header.extend_from_slice(&random);
+        let cipher = cipher_for(passphrase, &random[..SALT_LEN])?;
+        let nonce = XNonce::try_from(&random[SALT_LEN..]).expect("nonce has the right length");
+        let plaintext = Zeroizing::new(ron::to_string(self).map_err(|err| {
+            Error::PlatformFailure(format!("Couldn't encode the archive: {err}").into())
+        })?);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: &header,
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::PlatformFailure("Couldn't encrypt the archive".into()))?;
+        header.extend_from_slice(&ciphertext);
+        Ok(header)
+    }
+
+    /// Decrypt an archive with a key derived from the given passphrase.
+    ///
+    /// Gives a `BadStoreFormat` error if the data is not an archive, or if it
+    /// can't be decrypted (because the passphrase is wrong or the data has
+    /// been altered).
+    pub fn open(data: &[u8], passphrase: &str) -> Result<Self> {
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) {
+            return Err(Error::BadStoreFormat(
+                "This is not a keyring archive".to_string(),
+            ));
+        }
+        let version = data[MAGIC.len()];
+        if version != VERSION {
+            return Err(Error::BadStoreFormat(format!(
+                "Archive format version {version} is not supported"
+            )));
+        }
+        let (header, ciphertext) = data.split_at(HEADER_LEN);
+        let salt = &header[MAGIC.len() + 1..MAGIC.len() + 1 + SALT_LEN];
+        let nonce = XNonce::try_from(&header[HEADER_LEN - NONCE_LEN..])
+            .expect("nonce has the right length");
+        let cipher = cipher_for(passphrase, salt)?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: header,
+        };
+        let plaintext = Zeroizing::new(cipher.decrypt(&nonce, payload).map_err(|_| {
+            Error::BadStoreFormat(
+                "The archive can't be decrypted: the passphrase is wrong or the archive is damaged"
+                    .to_string(),
+            )
+        })?);
+        ron::de::from_bytes(&plaintext).map_err(|err| {
+            Error::BadStoreFormat(format!("The archive contents are invalid: {err}"))
+        })
+    }
+
+    /// Write the archived credentials to a store.
+    ///
+    /// Conflicts and dry runs are handled just as they are by [migrate](super::migrate);
+    /// errors writing individual credentials are recorded in the report.
+    pub fn restore(
+        &self,
+        to: &Arc<CredentialStore>,
+        options: &ImportOptions,
+    ) -> Result<MigrationReport> {
+        let mut report = MigrationReport {
+            dry_run: options.dry_run,
+            entries: Vec::new(),
+        };
+        for cred in &self.credentials {
+            let mut warnings = Vec::new();
+            let outcome = to
+                .build(&cred.service, &cred.user, None)
+                .and_then(|target| {
+                    copy_credential(
+                        &target,
+                        &cred.secret,
+                        &cred.attributes,
+                        options.on_conflict,
+                        options.dry_run,
+                        &mut warnings,
+                    )
+                })
+                .unwrap_or_else(|err| MigrationOutcome::Failed(err.to_string()));
+            report.entries.push(MigratedEntry {
+                service: cred.service.clone(),
+                user: cred.user.clone(),
+                outcome,
+                warnings,
+            });
+        }
+        Ok(report)
+    }
+}
+
+/// The Argon2id cost parameters of archive version 1: 19 MiB of memory,
+/// two passes, and one lane. These are fixed by the format (rather than
+/// left to the argon2 crate's defaults) so that archives written by one
+/// release can always be opened by another.
+const KDF_M_COST: u32 = 19 * 1024;
+const KDF_T_COST: u32 = 2;
+const KDF_P_COST: u32 = 1;
+
+fn cipher_for(passphrase: &str, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    let params = Params::new(KDF_M_COST, KDF_T_COST, KDF_P_COST, Some(key.len()))
+        .expect("archive key-derivation parameters are valid");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|err| Error::PlatformFailure(format!("Couldn't derive the key: {err}").into()))?;
+    Ok(XChaCha20Poly1305::new_from_slice(key.as_ref()).expect("key has the right length"))
+}
+
+/// Export the credentials matching a search query to an encrypted archive file.
+///
+/// The store is given as a store specification (see
+/// [build_store_from_spec](super::build_store_from_spec)). The archive is
+/// encrypted with a key derived from the passphrase, and the file (which
+/// is replaced if it exists) is only readable by its owner.
+///
+/// Returns the number of credentials exported.
+pub fn export(
+    from: &str,
+    query: &HashMap<&str, &str>,
+    path: &str,
+    passphrase: &str,
+) -> Result<usize> {
+    let store = build_store_from_spec(from)?;
+    let archive = Archive::from_store(&store, query)?;
+    let data = archive.seal(passphrase)?;
+    write_private(Path::new(path), &data)?;
+    Ok(archive.credentials.len())
+}
+
+/// Import the credentials in an encrypted archive file into a store.
+///
+/// The store is given as a store specification (see
+/// [build_store_from_spec](super::build_store_from_spec)). See [Archive::open]
+/// for the errors given if the archive can't be decrypted, and [Archive::restore]
+/// for how the credentials are written.
+pub fn import(
+    path: &str,
+    to: &str,
+    passphrase: &str,
+    options: &ImportOptions,
+) -> Result<MigrationReport> {
+    let data =
+        std::fs::read(path).map_err(|err| Error::Invalid(path.to_string(), err.to_string()))?;
+    let archive = Archive::open(&data, passphrase)?;
+    let store = build_store_from_spec(to)?;
+    archive.restore(&store, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    fn sample_archive() -> Archive {
+        let store = build_sample_store(&HashMap::new()).unwrap();
+        let entry = new_entry_in(&store, "archive-svc", "u1").unwrap();
+        entry.set_secret(b"\x00\xffsecret").unwrap();
+        entry
+            .update_attributes(&HashMap::from([("comment", "archived")]))
+            .unwrap();
+        new_entry_in(&store, "archive-svc", "u2")
+            .unwrap()
+            .set_password("password")
+            .unwrap();
+        let archive = Archive::from_store(&store, &HashMap::new()).unwrap();
+        assert_eq!(archive.vendor, store.vendor());
+        assert_eq!(archive.credentials.len(), 2);
+        archive
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let archive = sample_archive();
+        let data = archive.seal("correct horse").unwrap();
+        assert!(data.starts_with(MAGIC));
+        assert_eq!(Archive::open(&data, "correct horse").unwrap(), archive);
+        assert!(matches!(
+            Archive::open(&data, "wrong horse"),
+            Err(Error::BadStoreFormat(_))
+        ));
+        let mut altered = data.clone();
+        altered[MAGIC.len() + 1] ^= 1;
+        assert!(matches!(
+            Archive::open(&altered, "correct horse"),
+            Err(Error::BadStoreFormat(_))
+        ));
+        assert!(matches!(
+            Archive::open(b"not an archive", "correct horse"),
+            Err(Error::BadStoreFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore() {
+        let archive = sample_archive();
+        let store = build_sample_store(&HashMap::new()).unwrap();
+        let existing = new_entry_in(&store, "archive-svc", "u2").unwrap();
+        existing.set_password("existing").unwrap();
+        let report = archive.restore(&store, &ImportOptions::default()).unwrap();
+        assert_eq!(report.failures(), 0);
+        let restored = new_entry_in(&store, "archive-svc", "u1").unwrap();
+        assert_eq!(restored.get_secret().unwrap(), b"\x00\xffsecret");
+        assert_eq!(restored.get_attributes().unwrap()["comment"], "archived");
+        assert_eq!(existing.get_password().unwrap(), "existing");
+        let options = ImportOptions {
+            on_conflict: ConflictPolicy::Overwrite,
+            ..Default::default()
+        };
+        archive.restore(&store, &options).unwrap();
+        assert_eq!(existing.get_password().unwrap(), "password");
+    }
+
+    #[test]
+    fn test_export_and_import() {
+        let dir = std::env::temp_dir();
+        let suffix = fastrand::u64(..);
+        let backing = dir.join(format!("keyring-test-archive-store-{suffix}.ron"));
+        let path = dir.join(format!("keyring-test-archive-{suffix}.bin"));
+        let path = path.to_str().unwrap();
+        let spec = format!("sample:backing-file={}", backing.to_str().unwrap());
+        {
+            let store = build_store_from_spec(&spec).unwrap();
+            let entry = new_entry_in(&store, "archive-file", "u1").unwrap();
+            entry.set_password("exported").unwrap();
+        }
+        let query = HashMap::from([("service", "archive-file")]);
+        std::fs::write(path, "an existing file").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let readable = std::fs::Permissions::from_mode(0o644);
+            std::fs::set_permissions(path, readable).unwrap();
+        }
+        assert_eq!(export(&spec, &query, path, "passphrase").unwrap(), 1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(&backing).unwrap();
+        let options = ImportOptions::default();
+        let report = import(path, &spec, "passphrase", &options).unwrap();
+        assert_eq!(report.entries[0].outcome, MigrationOutcome::Copied);
+        let store = build_store_from_spec(&spec).unwrap();
+        let entry = new_entry_in(&store, "archive-file", "u1").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "exported");
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&backing).unwrap();
+    }
+}
@@ -0,0 +1,265 @@
+//! Async access to credential stores.
+//!
+//! Credential store operations block the calling thread, sometimes for a long
+//! time (while a daemon starts, say, or while an unlock prompt is showing), so
+//! they shouldn't be called directly from async code. The [AsyncEntry] type
+//! and the async functions in this module run them on a thread pool instead
+//! (that of the [blocking](https://crates.io/crates/blocking) crate), so they
+//! can be awaited from any executor, including tokio's.
+//!
+//! These futures are cancellation-safe: if one is dropped before it finishes
+//! (for example, because it lost a race with a timeout), the store operation
+//! either never starts or runs to completion in the background, so a credential
+//! is never left half-changed. An [AsyncEntry] can be given a timeout with
+//! [AsyncEntry::with_timeout]; operations that exceed it give the same
+//! error as those on a [TimeoutStore](super::TimeoutStore) (see
+//! [is_timeout](super::is_timeout)). Creating and searching for entries, and
+//! choosing the default store, are given the timeout set by
+//! [set_operation_timeout](super::set_operation_timeout), if there is one.
+//!
+//! This module is only available with the `async` feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use blocking::unblock;
+use keyring_core::{Entry, Error, Result, attributes::externalize_attributes};
+use zeroize::Zeroizing;
+
+use super::{OperationTimedOut, internalize, operation_timeout};
+
+/// An entry whose operations can be awaited. See the module docs.
+#[derive(Debug, Clone)]
+pub struct AsyncEntry {
+    inner: Arc<Entry>,
+    timeout: Option<Duration>,
+}
+
+impl From<Entry> for AsyncEntry {
+    fn from(entry: Entry) -> Self {
+        AsyncEntry {
+            inner: Arc::new(entry),
+            timeout: None,
+        }
+    }
+}
+
+/// Run a blocking operation on the thread pool, giving up on it after the timeout (if any).
+async fn run<T: Send + 'static>(
+    timeout: Option<Duration>,
+    operation: &str,
+    op: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let task = unblock(op);
+    let Some(timeout) = timeout else {
+        return task.await;
+    };
+    let expired = async {
+        async_io::Timer::after(timeout).await;
+        Err(Error::NoStorageAccess(Box::new(OperationTimedOut {
+            operation: operation.to_string(),
+            timeout,
+        })))
+    };
+    futures_lite::future::or(task, expired).await
+}
+
+impl AsyncEntry {
+    /// Create an entry for the given service and user in the default store.
+    ///
+    /// See [Entry::new] for the errors this can give.
+    pub async fn new(service: &str, user: &str) -> Result<AsyncEntry> {
+        let (service, user) = (service.to_string(), user.to_string());
+        let entry = run(operation_timeout(), "new", move || {
+            Entry::new(&service, &user)
+        })
+        .await?;
+        Ok(entry.into())
+    }
+
+    /// Create an entry for the given service and user in the default store,
+    /// using the given modifiers.
+    ///
+    /// See [Entry::new_with_modifiers] for the errors this can give.
+    pub async fn new_with_modifiers(
+        service: &str,
+        user: &str,
+        modifiers: &HashMap<&str, &str>,
+    ) -> Result<AsyncEntry> {
+        let (service, user) = (service.to_string(), user.to_string());
+        let modifiers = externalize_attributes(modifiers);
+        let entry = run(operation_timeout(), "new", move || {
+            Entry::new_with_modifiers(&service, &user, &internalize(Some(&modifiers)))
+        })
+        .await?;
+        Ok(entry.into())
+    }
+
+    /// Search the default store for entries matching the spec.
+    ///
+    /// See [Entry::search] for the errors this can give.
+    pub async fn search(spec: &HashMap<&str, &str>) -> Result<Vec<AsyncEntry>> {
+        let spec = externalize_attributes(spec);
+        let found = run(operation_timeout(), "search", move || {
+            Entry::search(&internalize(Some(&spec)))
+        })
+        .await?;
+        Ok(found.into_iter().map(AsyncEntry::from).collect())
+    }
+
+    /// Give up on this entry's operations if they take longer than the timeout.
+    ///
+    /// The timeout also applies to the entries returned by [get_credential](AsyncEntry::get_credential).
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        AsyncEntry {
+            timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// The (blocking) entry that this entry wraps.
+    pub fn entry(&self) -> &Entry {
+        &self.inner
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: &str,
+        op: impl FnOnce(&Entry) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let inner = self.inner.clone();
+        run(self.timeout, operation, move || op(&inner)).await
+    }
+
+    /// See [Entry::set_password].
+    pub async fn set_password(&self, password: &str) -> Result<()> {
+        let password = Zeroizing::new(password.to_string());
+        self.run("set_password", move |e| e.set_password(&password))
+            .await
+    }
+
+    /// See [Entry::set_secret].
+    pub async fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        let secret = Zeroizing::new(secret.to_vec());
+        self.run("set_secret", move |e| e.set_secret(&secret)).await
+    }
+
+    /// See [Entry::get_password].
+    pub async fn get_password(&self) -> Result<String> {
+        self.run("get_password", |e| e.get_password()).await
+    }
+
+    /// See [Entry::get_secret].
+    pub async fn get_secret(&self) -> Result<Vec<u8>> {
+        self.run("get_secret", |e| e.get_secret()).await
+    }
+
+    /// See [Entry::get_attributes].
+    pub async fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.run("get_attributes", |e| e.get_attributes()).await
+    }
+
+    /// See [Entry::update_attributes].
+    pub async fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        let attributes = externalize_attributes(attributes);
+        self.run("update_attributes", move |e| {
+            e.update_attributes(&internalize(Some(&attributes)))
+        })
+        .await
+    }
+
+    /// See [Entry::delete_credential].
+    pub async fn delete_credential(&self) -> Result<()> {
+        self.run("delete_credential", |e| e.delete_credential())
+            .await
+    }
+
+    /// See [Entry::get_credential].
+    pub async fn get_credential(&self) -> Result<AsyncEntry> {
+        let inner = self.run("get_credential", |e| e.get_credential()).await?;
+        Ok(AsyncEntry {
+            inner: Arc::new(inner),
+            timeout: self.timeout,
+        })
+    }
+
+    /// See [Entry::get_specifiers]. (This doesn't block, so it isn't async.)
+    pub fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+}
+
+/// Set the default store to one of the known stores in its default configuration,
+/// without blocking the executor.
+///
+/// See [use_named_store](super::use_named_store), which this runs on the thread pool.
+pub async fn use_named_store_async(name: &str) -> Result<()> {
+    let name = name.to_string();
+    run(operation_timeout(), "use_named_store", move || {
+        super::use_named_store(&name)
+    })
+    .await
+}
+
+/// Set the default store to one of the known stores in the specified configuration,
+/// without blocking the executor.
+///
+/// See [use_named_store_with_modifiers](super::use_named_store_with_modifiers),
+/// which this runs on the thread pool.
+pub async fn use_named_store_with_modifiers_async(
+    name: &str,
+    modifiers: &HashMap<&str, &str>,
+) -> Result<()> {
+    let name = name.to_string();
+    let modifiers = externalize_attributes(modifiers);
+    run(operation_timeout(), "use_named_store", move || {
+        super::use_named_store_with_modifiers(&name, &internalize(Some(&modifiers)))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{DefaultStoreGuard, is_timeout};
+    use futures_lite::future::block_on;
+
+    #[test]
+    fn test_async_entry() {
+        let _guard = super::super::tests::DEFAULT_STORE_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let _restore = DefaultStoreGuard::capture();
+        block_on(async {
+            let modifiers = HashMap::from([("persist", "false")]);
+            use_named_store_with_modifiers_async("sample", &modifiers)
+                .await
+                .unwrap();
+            let entry = AsyncEntry::new("svc", "usr").await.unwrap();
+            // futures must be Send to be spawned on multi-threaded executors
+            fn assert_send<T: Send>(_: &T) {}
+            assert_send(&entry.get_password());
+            entry.set_password("async").await.unwrap();
+            assert_eq!(entry.get_password().await.unwrap(), "async");
+            let found = AsyncEntry::search(&HashMap::from([("service", "svc")]))
+                .await
+                .unwrap();
+            assert_eq!(found.len(), 1);
+            found[0].delete_credential().await.unwrap();
+            assert!(matches!(entry.get_password().await, Err(Error::NoEntry)));
+        });
+    }
+
+    #[test]
+    fn test_async_timeout() {
+        let err = block_on(run(Some(Duration::from_millis(20)), "sleep", || {
+            std::thread::sleep(Duration::from_secs(2));
+            Ok(())
+        }))
+        .unwrap_err();
+        assert!(is_timeout(&err));
+        let quick = block_on(run(Some(Duration::from_secs(10)), "add", || Ok(1 + 1)));
+        assert_eq!(quick.unwrap(), 2);
+    }
+}
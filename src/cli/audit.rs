@@ -0,0 +1,345 @@
+//! A store wrapper that keeps an audit log of the operations on another store.
+//!
+//! Wrapping a store in an [AuditingStore] appends a record to a log file for
+//! every read, change, or search of a credential made through the wrapper.
+//! The log is in [JSON lines](https://jsonlines.org) format: each line is an
+//! object with these fields:
+//!
+//! - `timestamp`: when the operation finished, in RFC 3339 format (UTC).
+//! - `operation`: one of `get_secret`, `set_secret`, `get_attributes`,
+//!   `update_attributes`, `delete_credential`, or `search`.
+//! - `service` and `user`: the credential's service and user (for a search,
+//!   the `service` and `user` given in the search spec), or `null`.
+//! - `store`: the id of the wrapped store.
+//! - `pid` and `executable`: the process that made the operation.
+//! - `result`: `ok`, or the name of the error variant the operation gave
+//!   (such as `NoEntry`).
+//! - `found`: for a search that succeeded, the number of entries found.
+//! - `attempts`: if the wrapped store retries failed operations (see
+//!   [RetryingStore](super::RetryingStore)), the number of attempts made.
+//!
+//! Secrets and attribute values are never logged.
+//!
+//! If a record can't be written, the operation gives a `PlatformFailure`
+//! error instead of its result, so that no credential is read without being
+//! audited. (A change that was made to the store is not undone, though.)
+//!
+//! Any named store can be wrapped by giving it the `audit-log=<path>` modifier,
+//! and every store chosen by name can be wrapped by calling
+//! [enable_audit_log](super::enable_audit_log).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use super::retry::{take_attempts, variant_name};
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// A store that logs the operations on another store. See the module docs.
+pub struct AuditingStore {
+    inner: Arc<CredentialStore>,
+    log: Arc<AuditLog>,
+}
+
+impl std::fmt::Debug for AuditingStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditingStore")
+            .field("inner", &self.inner)
+            .field("path", &self.log.path)
+            .finish()
+    }
+}
+
+impl AuditingStore {
+    /// Wrap a store so that its operations are logged to the file at `path`.
+    ///
+    /// The file is created (readable only by its owner, on Unix) if it doesn't exist,
+    /// and records are appended to it if it does.
+    ///
+    /// Gives an `Invalid` error if the file can't be opened for appending.
+    pub fn new(inner: Arc<CredentialStore>, path: impl AsRef<Path>) -> Result<Arc<Self>> {
+        let log = AuditLog::open(path.as_ref(), inner.id())?;
+        Ok(Arc::new(AuditingStore {
+            inner,
+            log: Arc::new(log),
+        }))
+    }
+
+    /// The path of the audit log file.
+    pub fn path(&self) -> &Path {
+        &self.log.path
+    }
+
+    fn wrap(&self, inner: Entry) -> Entry {
+        Entry::new_with_credential(Arc::new(AuditingCredential {
+            inner,
+            log: self.log.clone(),
+        }))
+    }
+}
+
+impl CredentialStoreApi for AuditingStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    ///
+    /// Building an entry is not logged, since it doesn't touch any credential.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        Ok(self.wrap(self.inner.build(service, user, mods)?))
+    }
+
+    /// See the API docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        take_attempts();
+        let result = self.inner.search(spec);
+        let specifiers = (
+            spec.get("service").map(|s| s.to_string()),
+            spec.get("user").map(|u| u.to_string()),
+        );
+        let found = result.as_ref().ok().map(Vec::len);
+        self.log.record("search", specifiers, &result, found)?;
+        Ok(result?.into_iter().map(|e| self.wrap(e)).collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// One line of the audit log.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    operation: &'a str,
+    service: Option<String>,
+    user: Option<String>,
+    store: &'a str,
+    pid: u32,
+    executable: &'a str,
+    result: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    found: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<u32>,
+}
+
+/// An audit log file, shared by a store and its credentials.
+struct AuditLog {
+    path: PathBuf,
+    store: String,
+    executable: String,
+    file: Mutex<File>,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl AuditLog {
+    fn open(path: &Path, store: String) -> Result<Self> {
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let file = options.open(path).map_err(|err| {
+            Error::Invalid(
+                path.display().to_string(),
+                format!("can't be opened as an audit log: {err}"),
+            )
+        })?;
+        let executable = match std::env::current_exe() {
+            Ok(exe) => exe.display().to_string(),
+            Err(_) => String::new(),
+        };
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            store,
+            executable,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append a record of an operation and its result.
+    ///
+    /// Gives a `PlatformFailure` error if the record can't be written.
+    fn record<T>(
+        &self,
+        operation: &str,
+        (service, user): (Option<String>, Option<String>),
+        result: &Result<T>,
+        found: Option<usize>,
+    ) -> Result<()> {
+        let record = AuditRecord {
+            timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            operation,
+            service,
+            user,
+            store: &self.store,
+            pid: std::process::id(),
+            executable: &self.executable,
+            result: match result {
+                Ok(_) => "ok",
+                Err(err) => variant_name(err),
+            },
+            found,
+            attempts: take_attempts(),
+        };
+        let mut line =
+            serde_json::to_vec(&record).map_err(|err| Error::PlatformFailure(err.into()))?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .expect("Poisoned Mutex in keyring::cli audit log: please report a bug!")
+            .write_all(&line)
+            .map_err(|err| Error::PlatformFailure(err.into()))
+    }
+}
+
+/// A credential in an [AuditingStore].
+#[derive(Debug)]
+struct AuditingCredential {
+    inner: Entry,
+    log: Arc<AuditLog>,
+}
+
+impl AuditingCredential {
+    fn audit<T>(&self, operation: &str, op: impl FnOnce(&Entry) -> Result<T>) -> Result<T> {
+        take_attempts();
+        let result = op(&self.inner);
+        let specifiers = match self.inner.get_specifiers() {
+            Some((service, user)) => (Some(service), Some(user)),
+            None => (None, None),
+        };
+        self.log.record(operation, specifiers, &result, None)?;
+        result
+    }
+}
+
+impl CredentialApi for AuditingCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.audit("set_secret", |e| e.set_secret(secret))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.audit("get_secret", |e| e.get_secret())
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.audit("get_attributes", |e| e.get_attributes())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.audit("update_attributes", |e| e.update_attributes(attributes))
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.audit("delete_credential", |e| e.delete_credential())
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        Ok(Some(Arc::new(AuditingCredential {
+            inner: self.inner.get_credential()?,
+            log: self.log.clone(),
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    #[test]
+    fn test_audit_records() {
+        let dir = std::env::temp_dir().join(format!("keyring-audit-{}", fastrand::u64(..)));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let store: Arc<CredentialStore> = AuditingStore::new(inner.clone(), &path).unwrap();
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+        entry.set_password("top secret").unwrap();
+        let found = store.search(&HashMap::from([("service", "svc")])).unwrap();
+        found[0].delete_credential().unwrap();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!log.contains("top secret"));
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let summary: Vec<(&str, &str)> = records
+            .iter()
+            .map(|r| {
+                (
+                    r["operation"].as_str().unwrap(),
+                    r["result"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("get_secret", "NoEntry"),
+                ("set_secret", "ok"),
+                ("search", "ok"),
+                ("delete_credential", "ok"),
+            ]
+        );
+        assert_eq!(records[0]["service"], "svc");
+        assert_eq!(records[0]["user"], "usr");
+        assert_eq!(records[0]["store"], inner.id());
+        assert_eq!(records[0]["pid"], std::process::id());
+        assert!(records[2]["user"].is_null());
+        assert_eq!(records[2]["found"], 1);
+    }
+}
@@ -0,0 +1,358 @@
+//! A store wrapper that caches the secrets read from another store.
+//!
+//! Reading a secret from some stores is expensive: with the Secret Service,
+//! for example, every read is a D-Bus round trip. Wrapping such a store in a
+//! [CachingStore] means that repeated reads of the same credential within
+//! a configurable time-to-live are answered from memory. Cached secrets are
+//! wiped from memory when they are evicted or the cache is dropped.
+//!
+//! Writes and deletions made through the wrapper invalidate the affected
+//! cache entry, but changes made by other processes (or through other handles
+//! on the wrapped store) are only seen once the cached secret expires.
+//!
+//! Only entries created without modifiers are cached, because modifiers can
+//! make an entry refer to something other than the service and user it was
+//! created with. Entries returned by searches, and the credentials returned
+//! by `get_credential`, are not cached either (but writes through them still
+//! invalidate the cache).
+//!
+//! Any named store can be wrapped by giving it the `cache-ttl-secs` modifier
+//! (and, optionally, `cache-max-entries`).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use zeroize::Zeroizing;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// The default maximum number of secrets held by a [CachingStore].
+pub const DEFAULT_CACHE_MAX_ENTRIES: usize = 256;
+
+/// A store that caches secrets read from another store. See the module docs.
+pub struct CachingStore {
+    inner: Arc<CredentialStore>,
+    cache: Arc<SecretCache>,
+}
+
+impl std::fmt::Debug for CachingStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachingStore")
+            .field("inner", &self.inner)
+            .field("ttl", &self.cache.ttl)
+            .field("max_entries", &self.cache.max_entries)
+            .finish()
+    }
+}
+
+impl CachingStore {
+    /// Wrap a store so that secrets read from it are cached for the given time.
+    ///
+    /// At most `max_entries` secrets are cached; when the cache is full, the
+    /// secret that has been cached longest is evicted to make room.
+    pub fn new(inner: Arc<CredentialStore>, ttl: Duration, max_entries: usize) -> Arc<Self> {
+        Arc::new(CachingStore {
+            inner,
+            cache: Arc::new(SecretCache {
+                ttl,
+                max_entries,
+                secrets: Mutex::new(HashMap::new()),
+                generation: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Discard all cached secrets.
+    pub fn clear(&self) {
+        let mut secrets = self.cache.lock();
+        secrets.clear();
+        self.cache.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn wrap(&self, inner: Entry, cacheable: bool) -> Entry {
+        Entry::new_with_credential(Arc::new(CachingCredential {
+            cacheable,
+            inner,
+            cache: self.cache.clone(),
+        }))
+    }
+}
+
+impl CredentialStoreApi for CachingStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    ///
+    /// Entries built with modifiers are not cached.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let cacheable = mods.is_none_or(|mods| mods.is_empty());
+        Ok(self.wrap(self.inner.build(service, user, mods)?, cacheable))
+    }
+
+    /// See the API docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let found = self.inner.search(spec)?;
+        Ok(found.into_iter().map(|e| self.wrap(e, false)).collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+type CacheKey = (String, String);
+
+struct CachedSecret {
+    secret: Zeroizing<Vec<u8>>,
+    cached_at: Instant,
+}
+
+struct SecretCache {
+    ttl: Duration,
+    max_entries: usize,
+    secrets: Mutex<HashMap<CacheKey, CachedSecret>>,
+    /// Bumped (with the lock held) by every invalidation, so that a secret read
+    /// from the wrapped store before an invalidation isn't cached after it.
+    generation: AtomicU64,
+}
+
+impl SecretCache {
+    fn lock(&self) -> MutexGuard<'_, HashMap<CacheKey, CachedSecret>> {
+        self.secrets
+            .lock()
+            .expect("Poisoned Mutex in keyring::cli secret cache: please report a bug!")
+    }
+
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let mut secrets = self.lock();
+        match secrets.get(key) {
+            Some(cached) if cached.cached_at.elapsed() < self.ttl => Some(cached.secret.to_vec()),
+            Some(_) => {
+                secrets.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Cache a secret read when the cache was at the given generation,
+    /// unless the cache has been invalidated since.
+    fn insert(&self, key: CacheKey, secret: &[u8], generation: u64) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut secrets = self.lock();
+        if self.generation() != generation {
+            return;
+        }
+        if secrets.len() >= self.max_entries && !secrets.contains_key(&key) {
+            secrets.retain(|_, cached| cached.cached_at.elapsed() < self.ttl);
+            if secrets.len() >= self.max_entries {
+                let oldest = secrets
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.cached_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    secrets.remove(&oldest);
+                }
+            }
+        }
+        let cached = CachedSecret {
+            secret: Zeroizing::new(secret.to_vec()),
+            cached_at: Instant::now(),
+        };
+        secrets.insert(key, cached);
+    }
+
+    fn invalidate(&self, key: Option<CacheKey>) {
+        if let Some(key) = key {
+            let mut secrets = self.lock();
+            secrets.remove(&key);
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A credential in a [CachingStore].
+///
+/// Reads are answered from the cache only if `cacheable` is set, but writes
+/// always invalidate the cache entry for the credential's service and user.
+struct CachingCredential {
+    cacheable: bool,
+    inner: Entry,
+    cache: Arc<SecretCache>,
+}
+
+impl std::fmt::Debug for CachingCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachingCredential")
+            .field("cacheable", &self.cacheable)
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl CachingCredential {
+    fn cache_key(&self) -> Option<CacheKey> {
+        if self.cacheable {
+            self.inner.get_specifiers()
+        } else {
+            None
+        }
+    }
+
+    fn write(&self, op: impl Fn(&Entry) -> Result<()>) -> Result<()> {
+        let result = op(&self.inner);
+        self.cache.invalidate(self.inner.get_specifiers());
+        result
+    }
+}
+
+impl CredentialApi for CachingCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.write(|e| e.set_secret(secret))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        let key = self.cache_key();
+        if let Some(secret) = key.as_ref().and_then(|key| self.cache.get(key)) {
+            return Ok(secret);
+        }
+        let generation = self.cache.generation();
+        let secret = self.inner.get_secret()?;
+        if let Some(key) = key {
+            self.cache.insert(key, &secret, generation);
+        }
+        Ok(secret)
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.inner.get_attributes()
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.inner.update_attributes(attributes)
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.write(|e| e.delete_credential())
+    }
+
+    /// Returns an uncached wrapper for the underlying credential.
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        Ok(Some(Arc::new(CachingCredential {
+            cacheable: false,
+            inner: self.inner.get_credential()?,
+            cache: self.cache.clone(),
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    #[test]
+    fn test_reads_are_cached() {
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let cached: Arc<CredentialStore> =
+            CachingStore::new(inner.clone(), Duration::from_secs(60), 10);
+        let direct = new_entry_in(&inner, "svc", "usr").unwrap();
+        direct.set_password("first").unwrap();
+        let entry = new_entry_in(&cached, "svc", "usr").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "first");
+        direct.set_password("second").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "first");
+        entry.set_password("third").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "third");
+        direct.set_password("fourth").unwrap();
+        let found = cached.search(&HashMap::from([("service", "svc")])).unwrap();
+        assert_eq!(found[0].get_password().unwrap(), "fourth");
+        found[0].delete_credential().unwrap();
+        assert!(entry.get_password().is_err());
+    }
+
+    #[test]
+    fn test_expiry_and_eviction() {
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let store = CachingStore::new(inner.clone(), Duration::from_millis(50), 1);
+        let cached: Arc<CredentialStore> = store.clone();
+        let direct = new_entry_in(&inner, "svc", "usr").unwrap();
+        direct.set_password("first").unwrap();
+        let entry = new_entry_in(&cached, "svc", "usr").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "first");
+        direct.set_password("second").unwrap();
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(entry.get_password().unwrap(), "second");
+        let other = new_entry_in(&cached, "svc", "other").unwrap();
+        other.set_password("other").unwrap();
+        assert_eq!(other.get_password().unwrap(), "other");
+        assert_eq!(store.cache.lock().len(), 1);
+        direct.set_password("third").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "third");
+        store.clear();
+        assert!(store.cache.lock().is_empty());
+    }
+
+    #[test]
+    fn test_stale_reads_not_cached() {
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let store = CachingStore::new(inner, Duration::from_secs(60), 10);
+        let key = ("svc".to_string(), "usr".to_string());
+        // a read that started before a write finished must not be cached
+        let generation = store.cache.generation();
+        store.cache.invalidate(Some(key.clone()));
+        store.cache.insert(key.clone(), b"stale", generation);
+        assert_eq!(store.cache.get(&key), None);
+        let generation = store.cache.generation();
+        store.cache.insert(key.clone(), b"fresh", generation);
+        assert_eq!(store.cache.get(&key), Some(b"fresh".to_vec()));
+    }
+}
@@ -0,0 +1,539 @@
+//! A store that keeps each credential in a file of its own.
+//!
+//! Docker and Kubernetes deliver secrets as files, one per secret, under
+//! `/run/secrets` or a mounted volume. The `dir` store reads (and writes)
+//! credentials laid out that way. It is registered under the name `dir`,
+//! and takes these modifiers:
+//!
+//! - `root`: the directory holding the credentials. The default is `/run/secrets`.
+//! - `layout`: either `flat` (the default), in which the credential for a
+//!   service and user is in the file `<root>/<service>.<user>`, or `nested`,
+//!   in which it is in the file `<root>/<service>/<user>`. In the flat layout,
+//!   the user can be empty, in which case the file is `<root>/<service>`: that's
+//!   how to read a Docker secret, which is in a file named for the secret.
+//!
+//! (A [DirStore] built directly can also use [DirLayout::ServiceOnly], in which
+//! the whole file name is the service and the user is always empty.)
+//!
+//! The file holds the secret, exactly as set. Files (and directories) created
+//! by the store can be read only by their owner (on Unix), and files are
+//! replaced atomically, so readers never see a partly-written secret.
+//!
+//! A credential's attributes are kept in a hidden sidecar file next to it:
+//! the attributes of `<root>/<service>.<user>` are in `<root>/.<service>.<user>.attributes`.
+//!
+//! Searches walk the tree under the root, skipping hidden files and
+//! directories (such as Kubernetes' `..data` links), and return the credentials
+//! that have the `service`, `user`, and attribute values given in the spec.
+//!
+//! Service and user names can't be empty (except as noted above), start with
+//! a `.`, or contain path separators; in the flat layout, service names can't
+//! contain a `.` either.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use keyring_core::{
+    Credential, CredentialPersistence, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// The root used by a [DirStore] when none is given.
+pub const DEFAULT_DIR_ROOT: &str = "/run/secrets";
+
+/// How a [DirStore] lays out its credential files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirLayout {
+    /// The credential for a service and user is in `<root>/<service>.<user>`.
+    Flat,
+    /// The credential for a service and user is in `<root>/<service>/<user>`.
+    Nested,
+    /// The credential for a service is in `<root>/<service>`, and the user must
+    /// be empty. This is the layout of the systemd-creds store, whose file names
+    /// (such as `tls.key`) often contain a `.`.
+    ServiceOnly,
+}
+
+/// A store that keeps credentials in files under a directory. See the module docs.
+pub struct DirStore {
+    root: PathBuf,
+    layout: DirLayout,
+}
+
+impl std::fmt::Debug for DirStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirStore")
+            .field("vendor", &self.vendor())
+            .field("id", &self.id())
+            .finish()
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+/// Map an I/O error to the closest store error.
+fn io_error(err: std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => Error::NoEntry,
+        std::io::ErrorKind::PermissionDenied => Error::NoStorageAccess(err.into()),
+        _ => Error::PlatformFailure(err.into()),
+    }
+}
+
+impl DirStore {
+    /// Create a store with credentials under the given root.
+    ///
+    /// The root doesn't have to exist: it is created when a credential is first set.
+    pub fn new(root: impl AsRef<Path>, layout: DirLayout) -> Arc<Self> {
+        Arc::new(DirStore {
+            root: root.as_ref().to_path_buf(),
+            layout,
+        })
+    }
+
+    /// Create a store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let mut root = DEFAULT_DIR_ROOT;
+        let mut layout = DirLayout::Flat;
+        for (key, value) in modifiers {
+            match *key {
+                "root" => root = value,
+                "layout" => {
+                    layout = match *value {
+                        "flat" => DirLayout::Flat,
+                        "nested" => DirLayout::Nested,
+                        _ => return Err(invalid(key, "must be flat or nested")),
+                    }
+                }
+                _ => return Err(invalid(key, "unknown key")),
+            }
+        }
+        Ok(Self::new(root, layout))
+    }
+
+    /// The directory holding the store's credentials.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The file holding the credential for a service and user.
+    fn path(&self, service: &str, user: &str) -> Result<PathBuf> {
+        check_name("service", service)?;
+        match self.layout {
+            DirLayout::Flat => {
+                if service.contains('.') {
+                    return Err(invalid("service", "can't contain '.' in the flat layout"));
+                }
+                if user.is_empty() {
+                    return Ok(self.root.join(service));
+                }
+                check_name("user", user)?;
+                Ok(self.root.join(format!("{service}.{user}")))
+            }
+            DirLayout::Nested => {
+                check_name("user", user)?;
+                Ok(self.root.join(service).join(user))
+            }
+            DirLayout::ServiceOnly => {
+                if !user.is_empty() {
+                    return Err(invalid("user", "must be empty in the service-only layout"));
+                }
+                Ok(self.root.join(service))
+            }
+        }
+    }
+
+    /// The service and user of the credential in a file, given its path relative to the root.
+    fn specifiers(&self, relative: &[String]) -> Option<(String, String)> {
+        match (self.layout, relative) {
+            (DirLayout::Flat, [name]) => match name.split_once('.') {
+                Some((service, user)) => Some((service.to_string(), user.to_string())),
+                None => Some((name.clone(), String::new())),
+            },
+            (DirLayout::Nested, [service, user]) => Some((service.clone(), user.clone())),
+            (DirLayout::ServiceOnly, [name]) => Some((name.clone(), String::new())),
+            _ => None,
+        }
+    }
+
+    /// Add the credential files under `dir` to `found`.
+    fn walk(
+        &self,
+        dir: &Path,
+        relative: &mut Vec<String>,
+        found: &mut Vec<(PathBuf, String, String)>,
+    ) -> Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(io_error(err)),
+        };
+        for entry in entries {
+            let entry = entry.map_err(io_error)?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            // follow symbolic links, as Kubernetes uses them for secret files
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            relative.push(name);
+            if metadata.is_dir() {
+                if self.layout == DirLayout::Nested && relative.len() < 2 {
+                    self.walk(&path, relative, found)?;
+                }
+            } else if let Some((service, user)) = self.specifiers(relative) {
+                found.push((path, service, user));
+            }
+            relative.pop();
+        }
+        Ok(())
+    }
+}
+
+fn check_name(key: &str, name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') {
+        return Err(invalid(key, "can't be empty or start with '.'"));
+    }
+    if name.contains(['/', '\\', '\0']) {
+        return Err(invalid(key, "can't contain path separators"));
+    }
+    Ok(())
+}
+
+/// The sidecar file holding the attributes of the credential in a file.
+fn sidecar(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.attributes"))
+}
+
+/// Atomically replace the contents of a file with data that only its owner can read.
+pub(super) fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    replace_file(path, data, None)
+}
+
+/// Atomically replace the contents of an existing file, keeping its permissions.
+///
+/// If the path is a symbolic link, the file it points to is replaced (and the
+/// link is left alone).
+#[cfg(feature = "kdbx")]
+pub(super) fn rewrite_file(path: &Path, data: &[u8]) -> Result<()> {
+    let path = fs::canonicalize(path).map_err(io_error)?;
+    let permissions = fs::metadata(&path).map_err(io_error)?.permissions();
+    replace_file(&path, data, Some(permissions))
+}
+
+/// Write data to a temporary file next to a path (readable only by its owner
+/// unless other permissions are given) and then rename it over the path.
+fn replace_file(path: &Path, data: &[u8], permissions: Option<fs::Permissions>) -> Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir).map_err(io_error)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let temp = dir.join(format!(".{name}.{}-{count}.tmp", std::process::id()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let result = options
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            if let Some(permissions) = permissions {
+                file.set_permissions(permissions)?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.map_err(io_error)
+}
+
+impl CredentialStoreApi for DirStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("Directory store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id gives the store's root and layout.
+    fn id(&self) -> String {
+        format!("Dir: {} ({:?})", self.root.display(), self.layout)
+    }
+
+    /// See the API docs.
+    ///
+    /// No modifiers are accepted.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        if let Some(key) = mods.and_then(|mods| mods.keys().next()) {
+            return Err(invalid(key, "the dir store takes no entry modifiers"));
+        }
+        let path = self.path(service, user)?;
+        Ok(Entry::new_with_credential(Arc::new(DirCredential {
+            path,
+            specifiers: (service.to_string(), user.to_string()),
+            nested: self.layout == DirLayout::Nested,
+        })))
+    }
+
+    /// See the API docs.
+    ///
+    /// The spec's `service` and `user` values must match exactly, as must any
+    /// other values, which are compared with the credentials' attributes.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let mut found = Vec::new();
+        self.walk(&self.root, &mut Vec::new(), &mut found)?;
+        found.sort();
+        let mut result = Vec::new();
+        for (path, service, user) in found {
+            let credential = DirCredential {
+                path,
+                specifiers: (service, user),
+                nested: self.layout == DirLayout::Nested,
+            };
+            if credential.matches(spec)? {
+                result.push(Entry::new_with_credential(Arc::new(credential)));
+            }
+        }
+        Ok(result)
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        CredentialPersistence::UntilDelete
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [DirStore].
+#[derive(Debug)]
+struct DirCredential {
+    path: PathBuf,
+    specifiers: (String, String),
+    /// Whether the file is in a service directory (rather than directly in the root).
+    nested: bool,
+}
+
+impl DirCredential {
+    fn read_attributes(&self) -> Result<HashMap<String, String>> {
+        match fs::read_to_string(sidecar(&self.path)) {
+            Ok(text) => ron::from_str(&text).map_err(|err| {
+                Error::BadStoreFormat(format!(
+                    "attributes of {} can't be parsed: {err}",
+                    self.path.display()
+                ))
+            }),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(err) => Err(io_error(err)),
+        }
+    }
+
+    fn matches(&self, spec: &HashMap<&str, &str>) -> Result<bool> {
+        let (service, user) = &self.specifiers;
+        let mut attributes = None;
+        for (key, value) in spec {
+            let matched = match *key {
+                "service" => service == value,
+                "user" => user == value,
+                _ => {
+                    if attributes.is_none() {
+                        attributes = Some(self.read_attributes()?);
+                    }
+                    let attributes = attributes.as_ref().unwrap();
+                    attributes.get(*key).is_some_and(|v| v == value)
+                }
+            };
+            if !matched {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl CredentialApi for DirCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        write_private(&self.path, secret)
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        fs::read(&self.path).map_err(io_error)
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        fs::metadata(&self.path).map_err(io_error)?;
+        self.read_attributes()
+    }
+
+    /// Merges the given attributes into those in the sidecar file.
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        let mut current = self.get_attributes()?;
+        for (key, value) in attributes {
+            current.insert(key.to_string(), value.to_string());
+        }
+        let text = ron::to_string(&current).map_err(|err| Error::PlatformFailure(err.into()))?;
+        write_private(&sidecar(&self.path), text.as_bytes())
+    }
+
+    /// Removes the file and its sidecar, and (in the nested layout) the
+    /// service's directory if that leaves it empty.
+    fn delete_credential(&self) -> Result<()> {
+        fs::remove_file(&self.path).map_err(io_error)?;
+        match fs::remove_file(sidecar(&self.path)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(io_error(err)),
+            _ => {}
+        }
+        if self.nested
+            && let Some(dir) = self.path.parent()
+        {
+            // this fails (harmlessly) if the directory isn't empty
+            let _ = fs::remove_dir(dir);
+        }
+        Ok(())
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        fs::metadata(&self.path).map_err(io_error)?;
+        Ok(None)
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        Some(self.specifiers.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::new_entry_in;
+    use keyring_core::CredentialStore;
+
+    fn temp_root(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("keyring-dir-{name}-{}", fastrand::u64(..)))
+    }
+
+    #[test]
+    fn test_flat_layout() {
+        let root = temp_root("flat");
+        let store: Arc<CredentialStore> = DirStore::new(&root, DirLayout::Flat);
+        let entry = new_entry_in(&store, "svc", "usr.name").unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+        entry.set_password("in a file").unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("svc.usr.name")).unwrap(),
+            "in a file"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(root.join("svc.usr.name"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        entry
+            .update_attributes(&HashMap::from([("env", "prod")]))
+            .unwrap();
+        assert_eq!(entry.get_attributes().unwrap()["env"], "prod");
+        let found = store.search(&HashMap::from([("env", "prod")])).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].get_specifiers(),
+            Some(("svc".to_string(), "usr.name".to_string()))
+        );
+        assert!(
+            store
+                .search(&HashMap::from([("env", "dev")]))
+                .unwrap()
+                .is_empty()
+        );
+        entry.delete_credential().unwrap();
+        assert!(store.search(&HashMap::new()).unwrap().is_empty());
+        fs::write(root.join("db_password"), "from docker").unwrap();
+        let docker = new_entry_in(&store, "db_password", "").unwrap();
+        assert_eq!(docker.get_password().unwrap(), "from docker");
+        let found = store.search(&HashMap::new()).unwrap();
+        assert_eq!(
+            found[0].get_specifiers(),
+            Some(("db_password".to_string(), String::new()))
+        );
+        docker.delete_credential().unwrap();
+        assert!(new_entry_in(&store, "s.v.c", "usr").is_err());
+        assert!(new_entry_in(&store, "..", "usr").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_nested_layout() {
+        let root = temp_root("nested");
+        let store: Arc<CredentialStore> = DirStore::new_with_modifiers(&HashMap::from([
+            ("root", root.to_str().unwrap()),
+            ("layout", "nested"),
+        ]))
+        .unwrap();
+        for (service, user) in [("db", "admin"), ("db", "reader"), ("api", "token")] {
+            new_entry_in(&store, service, user)
+                .unwrap()
+                .set_password(user)
+                .unwrap();
+        }
+        assert_eq!(
+            fs::read_to_string(root.join("db").join("reader")).unwrap(),
+            "reader"
+        );
+        // hidden files, like Kubernetes' links, are skipped
+        fs::create_dir_all(root.join("..data")).unwrap();
+        fs::write(root.join("..data").join("stray"), "ignored").unwrap();
+        let found = store.search(&HashMap::from([("service", "db")])).unwrap();
+        let users: Vec<String> = found
+            .iter()
+            .map(|e| e.get_specifiers().unwrap().1)
+            .collect();
+        assert_eq!(users, vec!["admin", "reader"]);
+        assert_eq!(store.search(&HashMap::new()).unwrap().len(), 3);
+        new_entry_in(&store, "api", "token")
+            .unwrap()
+            .delete_credential()
+            .unwrap();
+        assert!(!root.join("api").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
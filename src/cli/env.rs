@@ -0,0 +1,412 @@
+//! A store that reads credentials from environment variables.
+//!
+//! CI systems and container runtimes usually hand secrets to programs in
+//! environment variables. The `env` store lets such programs read them through
+//! the same [Entry] calls they use everywhere else. It is registered under the
+//! name `env`, and takes these modifiers:
+//!
+//! - `template`: how a service and user are mapped to a variable name.
+//!   `{SERVICE}` and `{USER}` in the template are replaced by the service
+//!   and user names. The default is `KEYRING_{SERVICE}_{USER}`.
+//! - `normalize`: if `true` (the default), service and user names are
+//!   upper-cased and every character other than an ASCII letter, digit,
+//!   or underscore is replaced by an underscore before they go in the template.
+//!   So the `my-app` service's `ci` user has the secret in `KEYRING_MY_APP_CI`.
+//! - `writable`: if `true`, credentials can be set and deleted, but only in
+//!   an in-process overlay: the environment itself is never changed. Reads
+//!   see the overlay first, and a deletion hides the environment variable.
+//!   The default is `false`, which makes the store read-only.
+//!
+//! Searches take `service` and `user` values (both optional) and return an entry
+//! for each variable whose name fits the template. A service or user that is
+//! given must match exactly (after normalization); ones that aren't given are read
+//! off the variable name, so entries found this way have normalized names, and
+//! if the template puts them next to each other it can't tell where the service
+//! ends and the user begins: the service is taken to be as short as possible.
+//!
+//! Credentials in this store have no attributes.
+
+use std::any::Any;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use regex::Regex;
+use zeroize::Zeroizing;
+
+use keyring_core::{
+    Credential, CredentialPersistence, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// The template used by an [EnvStore] when none is given.
+pub const DEFAULT_ENV_TEMPLATE: &str = "KEYRING_{SERVICE}_{USER}";
+
+/// The in-process changes to the environment; `None` marks a deleted variable.
+type Overlay = Mutex<HashMap<String, Option<Zeroizing<Vec<u8>>>>>;
+
+/// A store backed by environment variables. See the module docs.
+pub struct EnvStore {
+    template: String,
+    normalize: bool,
+    overlay: Option<Arc<Overlay>>,
+}
+
+impl std::fmt::Debug for EnvStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnvStore")
+            .field("template", &self.template)
+            .field("normalize", &self.normalize)
+            .field("writable", &self.overlay.is_some())
+            .finish()
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+fn lock(overlay: &Overlay) -> MutexGuard<'_, HashMap<String, Option<Zeroizing<Vec<u8>>>>> {
+    overlay
+        .lock()
+        .expect("Poisoned Mutex in keyring::cli env store: please report a bug!")
+}
+
+impl EnvStore {
+    /// Create an env store with the given template.
+    ///
+    /// Gives an `Invalid` error if the template doesn't contain `{SERVICE}` or
+    /// `{USER}`, or contains either of them more than once.
+    pub fn new(template: &str, normalize: bool, writable: bool) -> Result<Arc<Self>> {
+        let services = template.matches("{SERVICE}").count();
+        let users = template.matches("{USER}").count();
+        if services + users == 0 || services > 1 || users > 1 {
+            return Err(invalid(
+                "template",
+                "must contain {SERVICE} or {USER} (or both), each at most once",
+            ));
+        }
+        Ok(Arc::new(EnvStore {
+            template: template.to_string(),
+            normalize,
+            overlay: writable.then(|| Arc::new(Mutex::new(HashMap::new()))),
+        }))
+    }
+
+    /// Create an env store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let mut template = DEFAULT_ENV_TEMPLATE;
+        let mut normalize = true;
+        let mut writable = false;
+        for (key, value) in modifiers {
+            match *key {
+                "template" => template = value,
+                "normalize" => normalize = parse_bool(key, value)?,
+                "writable" => writable = parse_bool(key, value)?,
+                _ => return Err(invalid(key, "unknown key")),
+            }
+        }
+        Self::new(template, normalize, writable)
+    }
+
+    fn prepare(&self, name: &str) -> String {
+        if self.normalize {
+            name.chars()
+                .map(|c| match c {
+                    'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => c.to_ascii_uppercase(),
+                    _ => '_',
+                })
+                .collect()
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// The name of the variable holding the credential for a service and user.
+    fn variable(&self, service: &str, user: &str) -> Result<String> {
+        let name = self
+            .template
+            .replace("{SERVICE}", &self.prepare(service))
+            .replace("{USER}", &self.prepare(user));
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return Err(invalid(&name, "is not a valid environment variable name"));
+        }
+        Ok(name)
+    }
+
+    /// A regex matching the names of variables for the given service and user,
+    /// capturing those that aren't given.
+    fn pattern(&self, service: Option<&str>, user: Option<&str>) -> Result<Regex> {
+        let mut pattern = String::from("^");
+        let mut rest = self.template.as_str();
+        let mut lazy = true;
+        while let Some(start) = rest.find('{') {
+            let (literal, placeholder) = rest.split_at(start);
+            pattern.push_str(&regex::escape(literal));
+            let (name, given) = if placeholder.starts_with("{SERVICE}") {
+                ("service", service)
+            } else if placeholder.starts_with("{USER}") {
+                ("user", user)
+            } else {
+                pattern.push_str(&regex::escape("{"));
+                rest = &placeholder[1..];
+                continue;
+            };
+            match given {
+                Some(given) => pattern.push_str(&regex::escape(&self.prepare(given))),
+                None => {
+                    let quantifier = if lazy { "+?" } else { "+" };
+                    pattern.push_str(&format!("(?P<{name}>.{quantifier})"));
+                    lazy = false;
+                }
+            }
+            rest = &placeholder[name.len() + 2..];
+        }
+        pattern.push_str(&regex::escape(rest));
+        pattern.push('$');
+        Regex::new(&pattern).map_err(|err| invalid("template", &err.to_string()))
+    }
+
+    fn credential(&self, variable: String, service: String, user: String) -> Entry {
+        Entry::new_with_credential(Arc::new(EnvCredential {
+            variable,
+            specifiers: (service, user),
+            overlay: self.overlay.clone(),
+        }))
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(invalid(key, "must be true or false")),
+    }
+}
+
+impl CredentialStoreApi for EnvStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("Environment variable store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id gives the store's template.
+    fn id(&self) -> String {
+        format!("Env: {}", self.template)
+    }
+
+    /// See the API docs.
+    ///
+    /// No modifiers are accepted.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        if let Some(key) = mods.and_then(|mods| mods.keys().next()) {
+            return Err(invalid(key, "the env store takes no entry modifiers"));
+        }
+        let variable = self.variable(service, user)?;
+        Ok(self.credential(variable, service.to_string(), user.to_string()))
+    }
+
+    /// See the API docs.
+    ///
+    /// The spec can have `service` and `user` values; see the module docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        if let Some(key) = spec.keys().find(|key| !["service", "user"].contains(*key)) {
+            return Err(invalid(
+                key,
+                "the env store can only search by service and user",
+            ));
+        }
+        let (service, user) = (spec.get("service").copied(), spec.get("user").copied());
+        let pattern = self.pattern(service, user)?;
+        let mut names: BTreeSet<String> = std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .collect();
+        if let Some(overlay) = &self.overlay {
+            for (name, value) in lock(overlay).iter() {
+                match value {
+                    Some(_) => names.insert(name.clone()),
+                    None => names.remove(name),
+                };
+            }
+        }
+        let mut found = Vec::new();
+        for name in names {
+            let Some(captures) = pattern.captures(&name) else {
+                continue;
+            };
+            let part = |key: &str, given: Option<&str>| match captures.name(key) {
+                Some(part) => part.as_str().to_string(),
+                None => given.unwrap_or_default().to_string(),
+            };
+            let (service, user) = (part("service", service), part("user", user));
+            found.push(self.credential(name, service, user));
+        }
+        Ok(found)
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    ///
+    /// Environment variables (and the overlay) last only as long as the process.
+    fn persistence(&self) -> CredentialPersistence {
+        CredentialPersistence::ProcessOnly
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in an [EnvStore].
+struct EnvCredential {
+    variable: String,
+    specifiers: (String, String),
+    overlay: Option<Arc<Overlay>>,
+}
+
+impl std::fmt::Debug for EnvCredential {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EnvCredential")
+            .field("variable", &self.variable)
+            .field("specifiers", &self.specifiers)
+            .finish()
+    }
+}
+
+impl EnvCredential {
+    fn overlay(&self, operation: &str) -> Result<&Overlay> {
+        self.overlay.as_deref().ok_or_else(|| {
+            Error::NotSupportedByStore(format!(
+                "The env store is read-only, so credentials can't be {operation} (use writable=true for an in-process overlay)"
+            ))
+        })
+    }
+}
+
+impl CredentialApi for EnvCredential {
+    /// Sets the secret in the overlay, if the store is writable.
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        let overlay = self.overlay("set")?;
+        let secret = Zeroizing::new(secret.to_vec());
+        lock(overlay).insert(self.variable.clone(), Some(secret));
+        Ok(())
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        if let Some(overlay) = &self.overlay
+            && let Some(value) = lock(overlay).get(&self.variable)
+        {
+            return value.as_ref().map(|v| v.to_vec()).ok_or(Error::NoEntry);
+        }
+        match std::env::var_os(&self.variable) {
+            Some(value) => Ok(value.into_encoded_bytes()),
+            None => Err(Error::NoEntry),
+        }
+    }
+
+    /// Credentials in this store have no attributes, so this only checks that
+    /// the credential exists.
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.get_secret()?;
+        Ok(HashMap::new())
+    }
+
+    /// Hides the credential in the overlay, if the store is writable.
+    fn delete_credential(&self) -> Result<()> {
+        let overlay = self.overlay("deleted")?;
+        self.get_secret()?;
+        lock(overlay).insert(self.variable.clone(), None);
+        Ok(())
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        self.get_secret()?;
+        Ok(None)
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        Some(self.specifiers.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::new_entry_in;
+    use keyring_core::CredentialStore;
+
+    #[test]
+    fn test_variable_names() {
+        let store = EnvStore::new(DEFAULT_ENV_TEMPLATE, true, false).unwrap();
+        assert_eq!(store.variable("my-app", "ci").unwrap(), "KEYRING_MY_APP_CI");
+        let exact = EnvStore::new("{SERVICE}", false, false).unwrap();
+        assert!(exact.variable("a=b", "").is_err());
+        assert!(EnvStore::new("NO_PLACEHOLDER", true, false).is_err());
+        assert!(EnvStore::new("{USER}_{USER}", true, false).is_err());
+        let pattern = store.pattern(None, None).unwrap();
+        let captures = pattern.captures("KEYRING_APP_CI_BOT").unwrap();
+        assert_eq!(&captures["service"], "APP");
+        assert_eq!(&captures["user"], "CI_BOT");
+        let pattern = store.pattern(Some("app-ci"), None).unwrap();
+        assert_eq!(
+            &pattern.captures("KEYRING_APP_CI_BOT").unwrap()["user"],
+            "BOT"
+        );
+    }
+
+    #[test]
+    fn test_read_only_and_overlay() {
+        // PATH is set in any environment the tests run in
+        let path = std::env::var("PATH").unwrap();
+        let read_only: Arc<CredentialStore> = EnvStore::new("{SERVICE}", true, false).unwrap();
+        let entry = new_entry_in(&read_only, "path", "").unwrap();
+        assert_eq!(entry.get_password().unwrap(), path);
+        assert!(matches!(
+            entry.set_password("changed"),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let found = read_only
+            .search(&HashMap::from([("service", "path")]))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        let writable: Arc<CredentialStore> = EnvStore::new_with_modifiers(&HashMap::from([
+            ("template", "KEYRING_TEST_{SERVICE}_{USER}"),
+            ("writable", "true"),
+        ]))
+        .unwrap();
+        let entry = new_entry_in(&writable, "svc", "usr").unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+        entry.set_password("overlaid").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "overlaid");
+        assert!(std::env::var_os("KEYRING_TEST_SVC_USR").is_none());
+        let found = writable.search(&HashMap::new()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].get_specifiers(),
+            Some(("SVC".to_string(), "USR".to_string()))
+        );
+        found[0].delete_credential().unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+        assert!(writable.search(&HashMap::new()).unwrap().is_empty());
+        let hidden = EnvStore::new("{SERVICE}", true, true).unwrap();
+        let path_entry = new_entry_in(&(hidden as Arc<CredentialStore>), "path", "").unwrap();
+        path_entry.delete_credential().unwrap();
+        assert!(matches!(path_entry.get_password(), Err(Error::NoEntry)));
+        assert_eq!(std::env::var("PATH").unwrap(), path);
+    }
+}
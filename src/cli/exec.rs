@@ -0,0 +1,474 @@
+//! A store that delegates to a helper program.
+//!
+//! The `exec` store keeps no credentials itself: it runs a helper program for
+//! each operation, much as git runs credential helpers, so a new backend (a
+//! company's secret broker, say) can be added by writing a small executable
+//! in any language, even a shell script. It is registered under the name
+//! `exec`, and takes these modifiers:
+//!
+//! - `command`: the helper program. This is required.
+//! - `args`: arguments to give the helper before the operation, separated by spaces.
+//!
+//! # The helper protocol
+//!
+//! For each operation, the helper is run (without a shell) as
+//! `<command> <args> <operation>`, where the operation is one of `get`, `set`,
+//! `delete`, `attributes`, `update`, and `search`. Its standard input gets the
+//! request, as lines of the form `key=value`, and the input is then closed.
+//! Every request but `search` has `service=` and `user=` lines naming the credential.
+//!
+//! - `get` gives the credential's secret as a `secret=` line, in base64.
+//! - `set` has a `secret=` line, in base64, with the secret to store.
+//!   The credential is created if it doesn't exist.
+//! - `delete` deletes the credential.
+//! - `attributes` gives the credential's attributes, one `attr.<name>=<value>` line each.
+//! - `update` has one `attr.<name>=<value>` line for each attribute to set.
+//! - `search` has a `service=` line, a `user=` line, and `attr.<name>=<value>`
+//!   lines, but only for the values the search spec gives (so a request with
+//!   no `user=` line matches any user, while one with an empty `user=` line
+//!   matches only the empty user). It gives one record for each matching
+//!   credential, each with a `service=` and a `user=` line, and the records
+//!   separated by blank lines.
+//!
+//! The helper says how the operation went with its exit status:
+//!
+//! - 0: it succeeded.
+//! - 2: there is no such credential (a `NoEntry` error).
+//! - 3: the helper doesn't do that operation (a `NotSupportedByStore` error).
+//! - 4: the helper can't reach its storage, because it's locked, say
+//!   (a `NoStorageAccess` error).
+//!
+//! Any other status is a `PlatformFailure` error, with the helper's standard error as its message.
+//! Lines the helper doesn't understand should be ignored, by the store and by helpers,
+//! so that the protocol can grow.
+//!
+//! Because values are sent on lines, service names, user names, and attribute
+//! names and values can't contain line breaks, and attribute names can't contain `=`.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use base64::prelude::*;
+use keyring_core::{
+    Credential, CredentialPersistence, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+use zeroize::Zeroizing;
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+fn check_value(key: &str, value: &str) -> Result<()> {
+    if value.contains(['\n', '\r']) {
+        return Err(invalid(key, "can't contain line breaks"));
+    }
+    Ok(())
+}
+
+/// A store that runs a helper program for each operation. See the module docs.
+#[derive(Debug)]
+pub struct ExecStore {
+    helper: Arc<Helper>,
+}
+
+/// The helper program of an [ExecStore].
+#[derive(Debug)]
+struct Helper {
+    command: PathBuf,
+    args: Vec<String>,
+}
+
+impl Helper {
+    /// Run the helper for an operation, giving its output lines.
+    fn run(&self, operation: &str, request: &[(&str, &str)]) -> Result<Zeroizing<String>> {
+        let mut input = Zeroizing::new(String::new());
+        for (key, value) in request {
+            input.push_str(key);
+            input.push('=');
+            input.push_str(value);
+            input.push('\n');
+        }
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .arg(operation)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                Error::NoStorageAccess(
+                    format!("Couldn't run {}: {err}", self.command.display()).into(),
+                )
+            })?;
+        // the request is written from another thread, so that a helper that
+        // writes a lot of output before reading its input can't deadlock us
+        let output = std::thread::scope(|scope| {
+            if let Some(mut stdin) = child.stdin.take() {
+                let input = &input;
+                scope.spawn(move || {
+                    // a helper that doesn't read its request closes the pipe, which is fine
+                    let _ = stdin.write_all(input.as_bytes());
+                });
+            }
+            child.wait_with_output()
+        })
+        .map_err(|err| Error::PlatformFailure(err.into()))?;
+        match output.status.code() {
+            Some(0) => String::from_utf8(output.stdout)
+                .map(Zeroizing::new)
+                .map_err(|err| {
+                    let message =
+                        format!("{} gave output that isn't UTF-8", self.command.display());
+                    Error::BadDataFormat(err.into_bytes(), message.into())
+                }),
+            Some(2) => Err(Error::NoEntry),
+            Some(3) => Err(Error::NotSupportedByStore(format!(
+                "{} doesn't support {operation}",
+                self.command.display()
+            ))),
+            status => {
+                let message = format!(
+                    "{} {operation} failed ({}): {}",
+                    self.command.display(),
+                    status.map_or("killed".to_string(), |code| format!("status {code}")),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                if status == Some(4) {
+                    Err(Error::NoStorageAccess(message.into()))
+                } else {
+                    Err(Error::PlatformFailure(message.into()))
+                }
+            }
+        }
+    }
+}
+
+/// The `key=value` lines of some helper output (other lines are skipped).
+fn lines(output: &str) -> impl Iterator<Item = (&str, &str)> {
+    output.lines().filter_map(|line| line.split_once('='))
+}
+
+impl ExecStore {
+    /// Create a store that runs the given helper program with the given arguments.
+    pub fn new(command: impl Into<PathBuf>, args: Vec<String>) -> Arc<Self> {
+        Arc::new(ExecStore {
+            helper: Arc::new(Helper {
+                command: command.into(),
+                args,
+            }),
+        })
+    }
+
+    /// Create a store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let mut command = None;
+        let mut args = Vec::new();
+        for (key, value) in modifiers {
+            match *key {
+                "command" => command = Some(*value),
+                "args" => args = value.split_whitespace().map(String::from).collect(),
+                _ => return Err(invalid(key, "unknown key")),
+            }
+        }
+        match command {
+            Some(command) if !command.is_empty() => Ok(Self::new(command, args)),
+            _ => Err(invalid("command", "the helper program must be given")),
+        }
+    }
+}
+
+impl CredentialStoreApi for ExecStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("Exec store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id gives the helper program.
+    fn id(&self) -> String {
+        format!("Exec: {}", self.helper.command.display())
+    }
+
+    /// See the API docs.
+    ///
+    /// No modifiers are accepted.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        if let Some(key) = mods.and_then(|mods| mods.keys().next()) {
+            return Err(invalid(key, "the exec store takes no entry modifiers"));
+        }
+        check_value("service", service)?;
+        check_value("user", user)?;
+        Ok(Entry::new_with_credential(Arc::new(ExecCredential {
+            helper: self.helper.clone(),
+            specifiers: (service.to_string(), user.to_string()),
+        })))
+    }
+
+    /// See the API docs.
+    ///
+    /// The helper does the search, as described in the module docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let mut request = Vec::new();
+        for (key, value) in spec {
+            check_value(key, value)?;
+            match *key {
+                "service" | "user" => request.push((key.to_string(), *value)),
+                _ => request.push((attribute_key(key)?, *value)),
+            }
+        }
+        request.sort();
+        let request: Vec<(&str, &str)> = request.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        let output = self.helper.run("search", &request)?;
+        let mut found = Vec::new();
+        for record in output.split("\n\n") {
+            let (mut service, mut user) = (None, None);
+            for (key, value) in lines(record) {
+                match key {
+                    "service" => service = Some(value),
+                    "user" => user = Some(value),
+                    _ => {}
+                }
+            }
+            if let (Some(service), Some(user)) = (service, user) {
+                found.push(Entry::new_with_credential(Arc::new(ExecCredential {
+                    helper: self.helper.clone(),
+                    specifiers: (service.to_string(), user.to_string()),
+                })));
+            }
+        }
+        Ok(found)
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    ///
+    /// The store doesn't know how long its helper keeps credentials.
+    fn persistence(&self) -> CredentialPersistence {
+        CredentialPersistence::Unspecified
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// The request key for an attribute.
+fn attribute_key(name: &str) -> Result<String> {
+    if name.is_empty() || name.contains(['=', '\n', '\r']) {
+        return Err(invalid(
+            name,
+            "attribute names can't be empty or contain '=' or line breaks",
+        ));
+    }
+    Ok(format!("attr.{name}"))
+}
+
+/// A credential in an [ExecStore].
+#[derive(Debug)]
+struct ExecCredential {
+    helper: Arc<Helper>,
+    specifiers: (String, String),
+}
+
+impl ExecCredential {
+    fn run(&self, operation: &str, extra: &[(&str, &str)]) -> Result<Zeroizing<String>> {
+        let (service, user) = &self.specifiers;
+        let mut request = vec![("service", service.as_str()), ("user", user.as_str())];
+        request.extend_from_slice(extra);
+        self.helper.run(operation, &request)
+    }
+}
+
+impl CredentialApi for ExecCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        let encoded = Zeroizing::new(BASE64_STANDARD.encode(secret));
+        self.run("set", &[("secret", encoded.as_str())])?;
+        Ok(())
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        let output = self.run("get", &[])?;
+        let Some((_, encoded)) = lines(&output).find(|(key, _)| *key == "secret") else {
+            return Err(Error::PlatformFailure(
+                format!("{} get gave no secret", self.helper.command.display()).into(),
+            ));
+        };
+        BASE64_STANDARD.decode(encoded.trim()).map_err(|err| {
+            Error::BadDataFormat(
+                encoded.as_bytes().to_vec(),
+                format!("the secret isn't base64: {err}").into(),
+            )
+        })
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        let output = self.run("attributes", &[])?;
+        Ok(lines(&output)
+            .filter_map(|(key, value)| {
+                let name = key.strip_prefix("attr.")?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        let mut extra = Vec::new();
+        for (name, value) in attributes {
+            check_value(name, value)?;
+            extra.push((attribute_key(name)?, *value));
+        }
+        extra.sort();
+        let extra: Vec<(&str, &str)> = extra.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        self.run("update", &extra)?;
+        Ok(())
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.run("delete", &[])?;
+        Ok(())
+    }
+
+    /// Asks the helper for the credential's attributes, to check that it exists.
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        self.run("attributes", &[])?;
+        Ok(None)
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        Some(self.specifiers.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::cli::new_entry_in;
+    use keyring_core::CredentialStore;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A helper that keeps credentials in files under the directory given as its argument.
+    const STUB: &str = r#"#!/bin/sh
+dir="$1"
+service= user= secret= attrs=
+while IFS= read -r line; do
+    case "$line" in
+        service=*) service="${line#service=}" ;;
+        user=*) user="${line#user=}" ;;
+        secret=*) secret="${line#secret=}" ;;
+        attr.*) attrs="$attrs$line
+" ;;
+    esac
+done
+file="$dir/$service.$user"
+case "$2" in
+    get) [ -f "$file" ] || exit 2; echo "secret=$(cat "$file")" ;;
+    set) printf '%s' "$secret" > "$file" ;;
+    delete) [ -f "$file" ] || exit 2; rm -f "$file" "$file.attrs" ;;
+    attributes) [ -f "$file" ] || exit 2; cat "$file.attrs" 2>/dev/null; exit 0 ;;
+    update) [ -f "$file" ] || exit 2; printf '%s' "$attrs" >> "$file.attrs" ;;
+    search)
+        for f in "$dir"/*; do
+            name="${f##*/}"
+            case "$name" in *.attrs|'*') continue ;; esac
+            [ -z "$service" ] || [ "${name%%.*}" = "$service" ] || continue
+            printf 'service=%s\nuser=%s\n\n' "${name%%.*}" "${name#*.}"
+        done ;;
+    locked) echo "the vault is sealed" >&2; exit 4 ;;
+    *) exit 3 ;;
+esac
+"#;
+
+    #[test]
+    fn test_exec_store() {
+        let dir = std::env::temp_dir().join(format!("keyring-exec-{}", fastrand::u64(..)));
+        fs::create_dir_all(&dir).unwrap();
+        let helper = dir.join("helper.sh");
+        fs::write(&helper, STUB).unwrap();
+        fs::set_permissions(&helper, fs::Permissions::from_mode(0o755)).unwrap();
+        let data = dir.join("data");
+        fs::create_dir(&data).unwrap();
+        let modifiers = HashMap::from([
+            ("command", helper.to_str().unwrap()),
+            ("args", data.to_str().unwrap()),
+        ]);
+        let store: Arc<CredentialStore> = ExecStore::new_with_modifiers(&modifiers).unwrap();
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+        entry.set_secret(b"\x00binary\nsecret").unwrap();
+        assert_eq!(entry.get_secret().unwrap(), b"\x00binary\nsecret");
+        entry
+            .update_attributes(&HashMap::from([("env", "prod")]))
+            .unwrap();
+        assert_eq!(entry.get_attributes().unwrap()["env"], "prod");
+        new_entry_in(&store, "other", "usr")
+            .unwrap()
+            .set_password("other")
+            .unwrap();
+        let found = store.search(&HashMap::from([("service", "svc")])).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].get_specifiers(),
+            Some(("svc".to_string(), "usr".to_string()))
+        );
+        assert_eq!(store.search(&HashMap::new()).unwrap().len(), 2);
+        found[0].delete_credential().unwrap();
+        assert!(matches!(entry.get_credential(), Err(Error::NoEntry)));
+        assert!(matches!(
+            new_entry_in(&store, "svc", "two\nlines"),
+            Err(Error::Invalid(_, _))
+        ));
+        // operations the helper doesn't know, and helpers that fail
+        let helper = Helper {
+            command: helper.clone(),
+            args: vec![data.to_str().unwrap().to_string()],
+        };
+        assert!(matches!(
+            helper.run("rotate", &[]),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        match helper.run("locked", &[]) {
+            Err(Error::NoStorageAccess(err)) => {
+                assert!(err.to_string().contains("the vault is sealed"))
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_large_request_and_output() {
+        // a helper that writes all its output before reading any of its input
+        let helper = Helper {
+            command: PathBuf::from("/bin/sh"),
+            args: vec![
+                "-c".to_string(),
+                r"head -c 200000 /dev/zero | tr '\0' x; cat > /dev/null".to_string(),
+                "helper".to_string(),
+            ],
+        };
+        let secret = "y".repeat(200_000);
+        let output = helper.run("set", &[("secret", &secret)]).unwrap();
+        assert_eq!(output.len(), 200_000);
+    }
+}
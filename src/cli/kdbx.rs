@@ -0,0 +1,580 @@
+//! A store that keeps credentials in a KeePass database.
+//!
+//! The `kdbx` store reads and writes a KeePass database file in the KDBX4
+//! format (as written by KeePass 2.x, KeePassXC, and KeePassDX), so that
+//! credentials kept by a team in a shared database can be used by programs
+//! and the credentials set by programs can be seen in KeePass. It is registered
+//! under the name `kdbx` (when the `kdbx` feature is enabled), and takes these
+//! modifiers:
+//!
+//! - `path`: the database file, which must already exist. This is required.
+//! - `password`: the database's master password.
+//! - `keyfile`: the path of the database's key file.
+//!
+//! At least one of `password` and `keyfile` must be given, and together they
+//! must make up the database's composite key.
+//!
+//! A credential for a service and user is a database entry (in any group but
+//! the recycle bin) with the service as its title and the user as its user name.
+//! Its secret is the entry's password, so it must be UTF-8. Its attributes are
+//! the entry's custom (string) fields; the standard fields (title, user name,
+//! password, URL, and notes) can't be read or changed as attributes. New entries
+//! are added to the database's root group, and deleted entries are removed
+//! (rather than moved to the recycle bin). Changes to existing entries are
+//! recorded in the entries' history, as KeePass would.
+//!
+//! The database is decrypted when the store is built, and again whenever the
+//! file is changed by another program. Each change made by the store saves
+//! the whole database, re-encrypted with its own settings, replacing the file
+//! atomically, so that neither KeePass nor another process ever sees a
+//! partly-written database. The new file keeps the old one's permissions,
+//! and if the path is a symbolic link, the file it points to is replaced. (A change made by another program between the
+//! store's last read of the file and its save is lost, as it would be if two
+//! KeePass windows had the same database open.)
+//!
+//! Searches return the credentials that have the `service` (title), `user`
+//! (user name), and custom field values given in the spec.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+use keepass::{
+    Database, DatabaseKey,
+    config::DatabaseVersion,
+    db::{DatabaseOpenError, EntryId, EntryRef, fields},
+};
+use keyring_core::{
+    Credential, CredentialPersistence, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+use super::dir::rewrite_file;
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+/// Map an error opening the database to the closest store error.
+fn open_error(path: &Path, err: DatabaseOpenError) -> Error {
+    match err {
+        DatabaseOpenError::Io(err) => match err.kind() {
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied => {
+                Error::NoStorageAccess(err.into())
+            }
+            _ => Error::PlatformFailure(err.into()),
+        },
+        DatabaseOpenError::Key(_) | DatabaseOpenError::Cryptography(_) => {
+            Error::NoStorageAccess(err.into())
+        }
+        err => Error::BadStoreFormat(format!("{} can't be read: {err}", path.display())),
+    }
+}
+
+/// A store that keeps credentials in a KeePass database file. See the module docs.
+#[derive(Debug)]
+pub struct KdbxStore {
+    file: Arc<KdbxFile>,
+}
+
+/// A decrypted database and the file it came from.
+struct KdbxFile {
+    path: PathBuf,
+    key: DatabaseKey,
+    loaded: Mutex<Loaded>,
+}
+
+impl std::fmt::Debug for KdbxFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the key is left out, as it holds the password
+        f.debug_struct("KdbxFile")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Loaded {
+    database: Database,
+    /// When the file was last modified, as of its last read or write by the store.
+    modified: Option<SystemTime>,
+}
+
+impl KdbxStore {
+    /// Open the database at the given path with the given password and/or key file.
+    ///
+    /// Fails with a `NoStorageAccess` error if the file can't be read or the
+    /// key is wrong, and with a `BadStoreFormat` error if the file isn't a KDBX4 database.
+    pub fn new(
+        path: impl AsRef<Path>,
+        password: Option<&str>,
+        keyfile: Option<&Path>,
+    ) -> Result<Arc<Self>> {
+        let mut key = DatabaseKey::new();
+        if let Some(password) = password {
+            key = key.with_password(password);
+        }
+        if let Some(keyfile) = keyfile {
+            let mut file =
+                fs::File::open(keyfile).map_err(|err| Error::NoStorageAccess(err.into()))?;
+            key = key
+                .with_keyfile(&mut file)
+                .map_err(|err| Error::NoStorageAccess(err.into()))?;
+        }
+        if key.is_empty() {
+            return Err(invalid("password", "a password or a keyfile is required"));
+        }
+        let path = path.as_ref().to_path_buf();
+        let loaded = KdbxFile::read(&path, &key)?;
+        Ok(Arc::new(KdbxStore {
+            file: Arc::new(KdbxFile {
+                path,
+                key,
+                loaded: Mutex::new(loaded),
+            }),
+        }))
+    }
+
+    /// Create a store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let mut path = None;
+        let mut password = None;
+        let mut keyfile = None;
+        for (key, value) in modifiers {
+            match *key {
+                "path" => path = Some(*value),
+                "password" => password = Some(*value),
+                "keyfile" => keyfile = Some(Path::new(value)),
+                _ => return Err(invalid(key, "unknown key")),
+            }
+        }
+        let Some(path) = path else {
+            return Err(invalid("path", "the database file must be given"));
+        };
+        Self::new(path, password, keyfile)
+    }
+
+    /// The database file.
+    pub fn path(&self) -> &Path {
+        &self.file.path
+    }
+}
+
+impl KdbxFile {
+    fn read(path: &Path, key: &DatabaseKey) -> Result<Loaded> {
+        let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut file = fs::File::open(path).map_err(|err| open_error(path, err.into()))?;
+        let database =
+            Database::open(&mut file, key.clone()).map_err(|err| open_error(path, err))?;
+        if !matches!(database.config.version, DatabaseVersion::KDB4(_)) {
+            return Err(Error::BadStoreFormat(format!(
+                "{} isn't a KDBX4 database",
+                path.display()
+            )));
+        }
+        Ok(Loaded { database, modified })
+    }
+
+    /// The decrypted database, re-read first if the file has changed since it was last read.
+    fn lock(&self) -> Result<MutexGuard<'_, Loaded>> {
+        let mut loaded = self
+            .loaded
+            .lock()
+            .expect("Poisoned Mutex in keyring::cli kdbx store: please report a bug!");
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != loaded.modified {
+            *loaded = Self::read(&self.path, &self.key)?;
+        }
+        Ok(loaded)
+    }
+
+    /// Save the database, atomically replacing the file.
+    fn save(&self, loaded: &mut Loaded) -> Result<()> {
+        let mut data = zeroize::Zeroizing::new(Vec::new());
+        loaded
+            .database
+            .save(&mut *data, self.key.clone())
+            .map_err(|err| Error::PlatformFailure(err.into()))?;
+        rewrite_file(&self.path, &data)?;
+        loaded.modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+/// The service and user of an entry, or `None` if the entry is in the recycle bin.
+fn specifiers(entry: &EntryRef<'_>) -> Option<(String, String)> {
+    let database = entry.database();
+    let recycle_bin = database.recycle_bin().map(|group| group.id());
+    let mut group = Some(entry.parent().id());
+    while let Some(id) = group {
+        if Some(id) == recycle_bin {
+            return None;
+        }
+        group = database
+            .group(id)
+            .and_then(|group| group.parent().map(|parent| parent.id()));
+    }
+    let title = entry.get_title().unwrap_or_default();
+    let username = entry.get_username().unwrap_or_default();
+    Some((title.to_string(), username.to_string()))
+}
+
+/// The custom (string) fields of an entry.
+fn custom_fields(entry: &EntryRef<'_>) -> HashMap<String, String> {
+    entry
+        .fields
+        .iter()
+        .filter(|(key, _)| !fields::KNOWN_FIELDS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.get().clone()))
+        .collect()
+}
+
+impl CredentialStoreApi for KdbxStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("KeePass store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id gives the database file.
+    fn id(&self) -> String {
+        format!("KeePass: {}", self.file.path.display())
+    }
+
+    /// See the API docs.
+    ///
+    /// No modifiers are accepted.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        if let Some(key) = mods.and_then(|mods| mods.keys().next()) {
+            return Err(invalid(key, "the kdbx store takes no entry modifiers"));
+        }
+        if service.is_empty() {
+            return Err(invalid("service", "can't be empty"));
+        }
+        Ok(Entry::new_with_credential(Arc::new(KdbxCredential {
+            file: self.file.clone(),
+            specifiers: (service.to_string(), user.to_string()),
+            id: None,
+        })))
+    }
+
+    /// See the API docs.
+    ///
+    /// The spec's `service` and `user` values must match the entries' titles
+    /// and user names exactly, and any other values must match their custom fields.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let loaded = self.file.lock()?;
+        let mut found = Vec::new();
+        for entry in loaded.database.iter_all_entries() {
+            let Some((service, user)) = specifiers(&entry) else {
+                continue;
+            };
+            let custom = custom_fields(&entry);
+            let matched = spec.iter().all(|(key, value)| match *key {
+                "service" => service == *value,
+                "user" => user == *value,
+                _ => custom.get(*key).is_some_and(|v| v == value),
+            });
+            if matched {
+                found.push((service, user, entry.id()));
+            }
+        }
+        found.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        Ok(found
+            .into_iter()
+            .map(|(service, user, id)| {
+                Entry::new_with_credential(Arc::new(KdbxCredential {
+                    file: self.file.clone(),
+                    specifiers: (service, user),
+                    id: Some(id),
+                }))
+            })
+            .collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        CredentialPersistence::UntilDelete
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [KdbxStore].
+#[derive(Debug)]
+struct KdbxCredential {
+    file: Arc<KdbxFile>,
+    specifiers: (String, String),
+    /// The database entry, if this credential is known to be a particular one.
+    id: Option<EntryId>,
+}
+
+impl KdbxCredential {
+    /// The database entry for this credential, if there is exactly one.
+    ///
+    /// Gives an `Ambiguous` error if there is more than one.
+    fn find(&self, database: &Database) -> Result<Option<EntryId>> {
+        if let Some(id) = self.id {
+            let live = database
+                .entry(id)
+                .is_some_and(|entry| specifiers(&entry).is_some());
+            return if live {
+                Ok(Some(id))
+            } else {
+                Err(Error::NoEntry)
+            };
+        }
+        let ids: Vec<EntryId> = database
+            .iter_all_entries()
+            .filter(|entry| specifiers(entry).as_ref() == Some(&self.specifiers))
+            .map(|entry| entry.id())
+            .collect();
+        match ids.as_slice() {
+            [] => Ok(None),
+            [id] => Ok(Some(*id)),
+            _ => Err(Error::Ambiguous(
+                ids.into_iter()
+                    .map(|id| {
+                        Entry::new_with_credential(Arc::new(KdbxCredential {
+                            file: self.file.clone(),
+                            specifiers: self.specifiers.clone(),
+                            id: Some(id),
+                        }))
+                    })
+                    .collect(),
+            )),
+        }
+    }
+
+    /// The database entry for this credential, which must exist.
+    fn existing(&self, database: &Database) -> Result<EntryId> {
+        self.find(database)?.ok_or(Error::NoEntry)
+    }
+}
+
+impl CredentialApi for KdbxCredential {
+    /// Sets the entry's password, adding an entry to the root group if there is none.
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        let Ok(password) = std::str::from_utf8(secret) else {
+            return Err(invalid("secret", "KeePass passwords must be UTF-8"));
+        };
+        let mut loaded = self.file.lock()?;
+        match self.find(&loaded.database)? {
+            Some(id) => {
+                let mut entry = loaded.database.entry_mut(id).ok_or(Error::NoEntry)?;
+                entry.edit_tracking(|entry| entry.set_protected(fields::PASSWORD, password));
+            }
+            None => {
+                let (service, user) = &self.specifiers;
+                loaded.database.root_mut().add_entry().edit(|entry| {
+                    entry.set_unprotected(fields::TITLE, service.as_str());
+                    entry.set_unprotected(fields::USERNAME, user.as_str());
+                    entry.set_protected(fields::PASSWORD, password);
+                });
+            }
+        }
+        self.file.save(&mut loaded)
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        let loaded = self.file.lock()?;
+        let id = self.existing(&loaded.database)?;
+        let entry = loaded.database.entry(id).ok_or(Error::NoEntry)?;
+        Ok(entry.get_password().unwrap_or_default().as_bytes().to_vec())
+    }
+
+    /// Gives the entry's custom fields.
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        let loaded = self.file.lock()?;
+        let id = self.existing(&loaded.database)?;
+        let entry = loaded.database.entry(id).ok_or(Error::NoEntry)?;
+        Ok(custom_fields(&entry))
+    }
+
+    /// Sets the entry's custom fields. The standard fields can't be set.
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        if let Some(key) = attributes
+            .keys()
+            .find(|key| fields::KNOWN_FIELDS.contains(key))
+        {
+            return Err(invalid(key, "is a standard KeePass field"));
+        }
+        let mut loaded = self.file.lock()?;
+        let id = self.existing(&loaded.database)?;
+        let mut entry = loaded.database.entry_mut(id).ok_or(Error::NoEntry)?;
+        entry.edit_tracking(|entry| {
+            for (key, value) in attributes {
+                entry.set_unprotected(*key, *value);
+            }
+        });
+        self.file.save(&mut loaded)
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        let mut loaded = self.file.lock()?;
+        let id = self.existing(&loaded.database)?;
+        let mut entry = loaded.database.entry_mut(id).ok_or(Error::NoEntry)?;
+        entry.track_changes().remove();
+        self.file.save(&mut loaded)
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let loaded = self.file.lock()?;
+        let id = self.existing(&loaded.database)?;
+        Ok(Some(Arc::new(KdbxCredential {
+            file: self.file.clone(),
+            specifiers: self.specifiers.clone(),
+            id: Some(id),
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        Some(self.specifiers.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::new_entry_in;
+    use keepass::config::{DatabaseConfig, KdfConfig};
+    use keyring_core::CredentialStore;
+
+    /// Write a database (with a cheap key derivation, so the tests are quick).
+    fn write_database(path: &Path, key: DatabaseKey) {
+        let mut config = DatabaseConfig::default();
+        if let KdfConfig::Argon2 {
+            iterations,
+            memory,
+            parallelism,
+            ..
+        } = &mut config.kdf_config
+        {
+            (*iterations, *memory, *parallelism) = (1, 64 * 1024, 1);
+        }
+        let mut database = Database::with_config(config);
+        database.root_mut().add_entry().edit(|entry| {
+            entry.set_unprotected(fields::TITLE, "db");
+            entry.set_unprotected(fields::USERNAME, "admin");
+            entry.set_protected(fields::PASSWORD, "from keepass");
+            entry.set_unprotected(fields::URL, "https://db.example.com");
+            entry.set_unprotected("env", "prod");
+        });
+        let mut file = fs::File::create(path).unwrap();
+        database.save(&mut file, key).unwrap();
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("keyring-kdbx-{name}-{}", fastrand::u64(..)))
+    }
+
+    #[test]
+    fn test_kdbx_store() {
+        let path = temp_path("store.kdbx");
+        write_database(&path, DatabaseKey::new().with_password("master"));
+        let modifiers = HashMap::from([("path", path.to_str().unwrap()), ("password", "master")]);
+        let store: Arc<CredentialStore> = KdbxStore::new_with_modifiers(&modifiers).unwrap();
+        let admin = new_entry_in(&store, "db", "admin").unwrap();
+        assert_eq!(admin.get_password().unwrap(), "from keepass");
+        assert_eq!(
+            admin.get_attributes().unwrap(),
+            HashMap::from([("env".to_string(), "prod".to_string())])
+        );
+        let reader = new_entry_in(&store, "db", "reader").unwrap();
+        assert!(matches!(reader.get_password(), Err(Error::NoEntry)));
+        reader.set_password("added").unwrap();
+        reader
+            .update_attributes(&HashMap::from([("env", "prod")]))
+            .unwrap();
+        admin.set_password("changed").unwrap();
+        assert!(matches!(
+            admin.update_attributes(&HashMap::from([("Password", "sneaky")])),
+            Err(Error::Invalid(_, _))
+        ));
+        // the changes are in the file, as another store (or KeePass) sees
+        let other: Arc<CredentialStore> = KdbxStore::new_with_modifiers(&modifiers).unwrap();
+        let found = other.search(&HashMap::from([("env", "prod")])).unwrap();
+        let users: Vec<String> = found
+            .iter()
+            .map(|e| e.get_specifiers().unwrap().1)
+            .collect();
+        assert_eq!(users, vec!["admin", "reader"]);
+        assert_eq!(found[0].get_password().unwrap(), "changed");
+        found[1].delete_credential().unwrap();
+        // and this store sees the other's change
+        assert!(matches!(reader.get_password(), Err(Error::NoEntry)));
+        assert_eq!(store.search(&HashMap::new()).unwrap().len(), 1);
+        let named = crate::cli::build_named_store_with_modifiers("kdbx", &modifiers).unwrap();
+        let info = crate::cli::StoreInfo::for_store(&named);
+        assert_eq!(info.modifiers["password"], "<redacted>");
+        let wrong = HashMap::from([("path", path.to_str().unwrap()), ("password", "wrong")]);
+        assert!(matches!(
+            KdbxStore::new_with_modifiers(&wrong),
+            Err(Error::NoStorageAccess(_))
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_kdbx_keyfile() {
+        let path = temp_path("keyfile.kdbx");
+        let keyfile = temp_path("keyfile.key");
+        fs::write(&keyfile, fastrand::u64(..).to_string()).unwrap();
+        let key = DatabaseKey::new()
+            .with_keyfile(&mut fs::File::open(&keyfile).unwrap())
+            .unwrap();
+        write_database(&path, key);
+        assert!(matches!(
+            KdbxStore::new(&path, None, None),
+            Err(Error::Invalid(_, _))
+        ));
+        let store: Arc<CredentialStore> = KdbxStore::new(&path, None, Some(&keyfile)).unwrap();
+        let entry = new_entry_in(&store, "db", "admin").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "from keepass");
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&keyfile).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_kdbx_save_keeps_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("shared.kdbx");
+        write_database(&path, DatabaseKey::new().with_password("master"));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let link = temp_path("link.kdbx");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        let store: Arc<CredentialStore> = KdbxStore::new(&link, Some("master"), None).unwrap();
+        let entry = new_entry_in(&store, "db", "admin").unwrap();
+        entry.set_password("changed").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        let reopened: Arc<CredentialStore> = KdbxStore::new(&path, Some("master"), None).unwrap();
+        let entry = new_entry_in(&reopened, "db", "admin").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "changed");
+        fs::remove_file(&link).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
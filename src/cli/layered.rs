@@ -0,0 +1,379 @@
+//! A composite store that reads from two stores but writes to only one.
+//!
+//! This is meant for transitions from one store to another: reads check
+//! the new store first and then fall back to the old one, while writes go
+//! only to the new store. It is registered under the name `layered`, and
+//! takes these modifiers:
+//!
+//! - `primary` (required): the name of the primary store.
+//! - `secondary` (required): the name of the secondary store.
+//! - `read-order`: either `primary-first` (the default) or `secondary-first`.
+//! - `write-target`: one of `primary` (the default), `secondary`, or `both`.
+//!
+//! Any modifier of the form `primary.key=val` or `secondary.key=val` is
+//! passed (as `key=val`) to the builder of the primary or secondary store.
+//! So, for example, `layered:primary=sqlite,primary.path=creds.db,secondary=secret-service`
+//! reads from an sqlite database in `creds.db` and then from the Secret Service.
+//!
+//! Reads fall through to the second store only when the first store has no
+//! matching credential; any other error from the first store is returned as is.
+//! Writes, attribute updates, and deletions affect only the write target(s).
+//! Searches return the matching credentials from both stores, in read order.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+use super::build_named_store_with_modifiers;
+
+/// The order in which a [LayeredStore] reads from its stores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOrder {
+    PrimaryFirst,
+    SecondaryFirst,
+}
+
+/// The store(s) that a [LayeredStore] writes to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteTarget {
+    Primary,
+    Secondary,
+    Both,
+}
+
+/// A store that reads from a primary and a secondary store. See the module docs.
+pub struct LayeredStore {
+    primary: Arc<CredentialStore>,
+    secondary: Arc<CredentialStore>,
+    read_order: ReadOrder,
+    write_target: WriteTarget,
+}
+
+impl std::fmt::Debug for LayeredStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LayeredStore")
+            .field("primary", &self.primary)
+            .field("secondary", &self.secondary)
+            .field("read_order", &self.read_order)
+            .field("write_target", &self.write_target)
+            .finish()
+    }
+}
+
+impl LayeredStore {
+    /// Create a layered store from two existing stores.
+    pub fn new(
+        primary: Arc<CredentialStore>,
+        secondary: Arc<CredentialStore>,
+        read_order: ReadOrder,
+        write_target: WriteTarget,
+    ) -> Arc<Self> {
+        Arc::new(LayeredStore {
+            primary,
+            secondary,
+            read_order,
+            write_target,
+        })
+    }
+
+    /// Create a layered store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let mut primary = None;
+        let mut secondary = None;
+        let mut read_order = ReadOrder::PrimaryFirst;
+        let mut write_target = WriteTarget::Primary;
+        let mut primary_mods = HashMap::new();
+        let mut secondary_mods = HashMap::new();
+        for (key, value) in modifiers {
+            match *key {
+                "primary" => primary = Some(*value),
+                "secondary" => secondary = Some(*value),
+                "read-order" => {
+                    read_order = match *value {
+                        "primary-first" => ReadOrder::PrimaryFirst,
+                        "secondary-first" => ReadOrder::SecondaryFirst,
+                        _ => return Err(invalid(key, "must be primary-first or secondary-first")),
+                    }
+                }
+                "write-target" => {
+                    write_target = match *value {
+                        "primary" => WriteTarget::Primary,
+                        "secondary" => WriteTarget::Secondary,
+                        "both" => WriteTarget::Both,
+                        _ => return Err(invalid(key, "must be primary, secondary, or both")),
+                    }
+                }
+                _ => {
+                    if let Some(inner) = key.strip_prefix("primary.") {
+                        primary_mods.insert(inner, *value);
+                    } else if let Some(inner) = key.strip_prefix("secondary.") {
+                        secondary_mods.insert(inner, *value);
+                    } else {
+                        return Err(invalid(key, "unknown key"));
+                    }
+                }
+            }
+        }
+        let primary = primary.ok_or_else(|| invalid("primary", "must be specified"))?;
+        let secondary = secondary.ok_or_else(|| invalid("secondary", "must be specified"))?;
+        Ok(Self::new(
+            build_named_store_with_modifiers(primary, &primary_mods)?,
+            build_named_store_with_modifiers(secondary, &secondary_mods)?,
+            read_order,
+            write_target,
+        ))
+    }
+
+    fn in_read_order<'a, T>(&self, primary: &'a T, secondary: &'a T) -> [&'a T; 2] {
+        match self.read_order {
+            ReadOrder::PrimaryFirst => [primary, secondary],
+            ReadOrder::SecondaryFirst => [secondary, primary],
+        }
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+impl CredentialStoreApi for LayeredStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("Layered store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id combines the ids of the primary and secondary stores.
+    fn id(&self) -> String {
+        format!(
+            "Layered: primary {}, secondary {}",
+            self.primary.id(),
+            self.secondary.id()
+        )
+    }
+
+    /// See the API docs.
+    ///
+    /// Any modifiers are passed to both stores.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let primary = Arc::new(self.primary.build(service, user, mods)?);
+        let secondary = Arc::new(self.secondary.build(service, user, mods)?);
+        let reads = self
+            .in_read_order(&primary, &secondary)
+            .map(Arc::clone)
+            .to_vec();
+        let writes = match self.write_target {
+            WriteTarget::Primary => vec![primary],
+            WriteTarget::Secondary => vec![secondary],
+            WriteTarget::Both => vec![primary, secondary],
+        };
+        Ok(Entry::new_with_credential(Arc::new(LayeredCredential {
+            reads,
+            writes,
+        })))
+    }
+
+    /// See the API docs.
+    ///
+    /// The spec is passed to both stores, and the results are concatenated in read order.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let mut result = Vec::new();
+        for store in self.in_read_order(&self.primary, &self.secondary) {
+            result.extend(store.search(spec)?);
+        }
+        Ok(result)
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the persistence of the (first) write target.
+    fn persistence(&self) -> CredentialPersistence {
+        match self.write_target {
+            WriteTarget::Secondary => self.secondary.persistence(),
+            _ => self.primary.persistence(),
+        }
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [LayeredStore].
+///
+/// Reads try each of the `reads` entries in turn, and writes go to all of the `writes`.
+#[derive(Debug)]
+struct LayeredCredential {
+    reads: Vec<Arc<Entry>>,
+    writes: Vec<Arc<Entry>>,
+}
+
+impl LayeredCredential {
+    fn read<T>(&self, op: impl Fn(&Entry) -> Result<T>) -> Result<T> {
+        for entry in &self.reads {
+            match op(entry) {
+                Err(Error::NoEntry) => continue,
+                result => return result,
+            }
+        }
+        Err(Error::NoEntry)
+    }
+
+    fn write(&self, op: impl Fn(&Entry) -> Result<()>) -> Result<()> {
+        for entry in &self.writes {
+            op(entry)?;
+        }
+        Ok(())
+    }
+}
+
+impl CredentialApi for LayeredCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.write(|e| e.set_secret(secret))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.read(|e| e.get_secret())
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.read(|e| e.get_attributes())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.write(|e| e.update_attributes(attributes))
+    }
+
+    /// Deletes the credential from the write target(s).
+    ///
+    /// When writing to both stores, this succeeds if either store had a credential.
+    fn delete_credential(&self) -> Result<()> {
+        let mut found = false;
+        for entry in &self.writes {
+            match entry.delete_credential() {
+                Ok(()) => found = true,
+                Err(Error::NoEntry) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if found { Ok(()) } else { Err(Error::NoEntry) }
+    }
+
+    /// Returns a wrapper for the first credential found in read order.
+    ///
+    /// The wrapper reads from and writes to only that credential.
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let entry = Arc::new(self.read(|e| e.get_credential())?);
+        Ok(Some(Arc::new(LayeredCredential {
+            reads: vec![entry.clone()],
+            writes: vec![entry],
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.reads.first().and_then(|e| e.get_specifiers())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    fn sample() -> Arc<CredentialStore> {
+        build_sample_store(&HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn test_read_fallback_and_write_target() {
+        let (primary, secondary) = (sample(), sample());
+        let layered: Arc<CredentialStore> = LayeredStore::new(
+            primary.clone(),
+            secondary.clone(),
+            ReadOrder::PrimaryFirst,
+            WriteTarget::Primary,
+        );
+        let old = new_entry_in(&secondary, "svc", "usr").unwrap();
+        old.set_password("old").unwrap();
+        let entry = new_entry_in(&layered, "svc", "usr").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "old");
+        entry.set_password("new").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "new");
+        assert_eq!(old.get_password().unwrap(), "old");
+        let spec = HashMap::from([("service", "svc")]);
+        assert_eq!(layered.search(&spec).unwrap().len(), 2);
+        entry.delete_credential().unwrap();
+        assert_eq!(entry.get_password().unwrap(), "old");
+        assert!(matches!(entry.delete_credential(), Err(Error::NoEntry)));
+        let wrapper = entry.get_credential().unwrap();
+        wrapper.set_password("rewritten").unwrap();
+        assert_eq!(old.get_password().unwrap(), "rewritten");
+    }
+
+    #[test]
+    fn test_write_both_and_read_order() {
+        let (primary, secondary) = (sample(), sample());
+        let layered: Arc<CredentialStore> = LayeredStore::new(
+            primary.clone(),
+            secondary.clone(),
+            ReadOrder::SecondaryFirst,
+            WriteTarget::Both,
+        );
+        let entry = new_entry_in(&layered, "svc", "usr").unwrap();
+        entry.set_password("both").unwrap();
+        let in_primary = new_entry_in(&primary, "svc", "usr").unwrap();
+        let in_secondary = new_entry_in(&secondary, "svc", "usr").unwrap();
+        assert_eq!(in_primary.get_password().unwrap(), "both");
+        in_secondary.set_password("secondary").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "secondary");
+        in_secondary.delete_credential().unwrap();
+        entry.delete_credential().unwrap();
+        assert!(matches!(in_primary.get_password(), Err(Error::NoEntry)));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mods = HashMap::from([
+            ("primary", "sample"),
+            ("primary.persist", "false"),
+            ("secondary", "sample"),
+            ("secondary.persist", "false"),
+            ("read-order", "secondary-first"),
+            ("write-target", "both"),
+        ]);
+        let store = LayeredStore::new_with_modifiers(&mods).unwrap();
+        assert_eq!(store.read_order, ReadOrder::SecondaryFirst);
+        assert_eq!(store.write_target, WriteTarget::Both);
+        for (key, mods) in [
+            ("secondary", HashMap::from([("primary", "sample")])),
+            ("read-order", HashMap::from([("read-order", "random")])),
+            ("persist", HashMap::from([("persist", "false")])),
+        ] {
+            match LayeredStore::new_with_modifiers(&mods) {
+                Err(Error::Invalid(bad, _)) => assert_eq!(bad, key),
+                other => panic!("Bad modifiers {mods:?} gave {other:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Copying credentials from one store to another.
+//!
+//! The [migrate] function copies the secrets and attributes of all the
+//! credentials matching a search query from one store to another. It's
+//! what you want when moving a set of credentials between backends,
+//! e.g., from the Secret Service to an sqlite database.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use keyring_core::{CredentialStore, Entry, Error, Result};
+
+use super::{build_store_from_spec, internalize, modifiers, parse_store_spec, store_modifiers};
+
+/// What to do when a credential being copied already exists in the target store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Leave the existing credential alone.
+    #[default]
+    Skip,
+    /// Replace the existing credential's secret (and update its attributes).
+    Overwrite,
+}
+
+/// Options for [migrate].
+#[derive(Debug, Clone, Default)]
+pub struct MigrateOptions {
+    /// Report what would be done without changing either store.
+    pub dry_run: bool,
+    /// What to do with credentials that already exist in the target store.
+    pub on_conflict: ConflictPolicy,
+    /// Delete each credential from the source store once it has been copied.
+    /// (Credentials that are skipped or fail to copy are never deleted.)
+    pub delete_source: bool,
+}
+
+/// What happened (or, in a dry run, would happen) to one credential.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationOutcome {
+    /// The credential was copied to the target store.
+    Copied,
+    /// The credential was copied over an existing one in the target store.
+    Overwritten,
+    /// The credential already existed in the target store and was left alone.
+    Skipped,
+    /// The credential could not be copied, for the given reason.
+    Failed(String),
+}
+
+impl std::fmt::Display for MigrationOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationOutcome::Copied => write!(f, "copied"),
+            MigrationOutcome::Overwritten => write!(f, "overwritten"),
+            MigrationOutcome::Skipped => write!(f, "skipped (already exists)"),
+            MigrationOutcome::Failed(reason) => write!(f, "failed: {reason}"),
+        }
+    }
+}
+
+/// The report on one credential.
+#[derive(Debug, Clone)]
+pub struct MigratedEntry {
+    pub service: String,
+    pub user: String,
+    pub outcome: MigrationOutcome,
+    /// Problems that didn't stop the secret from being copied,
+    /// such as attributes the target store wouldn't accept.
+    pub warnings: Vec<String>,
+}
+
+/// The report on a migration: one entry per credential found in the source store.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationReport {
+    /// Whether this was a dry run, in which case neither store was changed.
+    pub dry_run: bool,
+    pub entries: Vec<MigratedEntry>,
+}
+
+impl MigrationReport {
+    /// The number of credentials that could not be copied.
+    pub fn failures(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e.outcome, MigrationOutcome::Failed(_)))
+            .count()
+    }
+}
+
+/// Copy the credentials matching a search query from one store to another.
+///
+/// The stores are given as store specifications (see [build_store_from_spec]),
+/// and the query is passed to the source store's search. For each credential found,
+/// its secret and attributes are copied to the credential with the same service and
+/// user in the target store. See [MigrateOptions] for how conflicts, dry runs, and
+/// deletion from the source are handled.
+///
+/// Gives an `Invalid` error if the two specifications are for the same store
+/// (they have the same name and, once defaults are filled in, the same modifiers). Errors
+/// building either store or doing the search are returned; errors copying
+/// individual credentials are recorded in the report.
+pub fn migrate(
+    from: &str,
+    to: &str,
+    query: &HashMap<&str, &str>,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    if canonical_spec(from)? == canonical_spec(to)? {
+        return Err(Error::Invalid(
+            to.to_string(),
+            "must be different from the source store".to_string(),
+        ));
+    }
+    let source = build_store_from_spec(from)?;
+    let target = build_store_from_spec(to)?;
+    migrate_between(&source, &target, query, options)
+}
+
+/// A store specification's canonical name and modifiers, so that two
+/// specifications for the same store compare equal.
+///
+/// Modifiers that aren't given are filled in with their declared defaults (if
+/// the store declares its modifiers), and wrapper modifiers given in snake case
+/// are put in kebab case.
+fn canonical_spec(spec: &str) -> Result<(String, HashMap<String, String>)> {
+    let (name, given) = parse_store_spec(spec)?;
+    let wrappers = modifiers::wrapper_modifiers();
+    let canonical_key = |key: String| {
+        let kebab = key.replace('_', "-");
+        if wrappers.iter().any(|w| w.key == kebab) {
+            kebab
+        } else {
+            key
+        }
+    };
+    let mut canonical: HashMap<String, String> = given
+        .into_iter()
+        .map(|(key, value)| (canonical_key(key), value))
+        .collect();
+    // an unknown store name is reported when the store is built
+    if let Ok(Some(specs)) = store_modifiers(&name) {
+        for spec in specs {
+            if let Some(default) = spec.default
+                && !spec.key.contains('<')
+            {
+                canonical.entry(canonical_key(spec.key)).or_insert(default);
+            }
+        }
+    }
+    Ok((name, canonical))
+}
+
+/// Copy the credentials matching a search query from one store handle to another.
+///
+/// This is [migrate] for stores that have already been built.
+pub fn migrate_between(
+    from: &Arc<CredentialStore>,
+    to: &Arc<CredentialStore>,
+    query: &HashMap<&str, &str>,
+    options: &MigrateOptions,
+) -> Result<MigrationReport> {
+    let mut report = MigrationReport {
+        dry_run: options.dry_run,
+        entries: Vec::new(),
+    };
+    for source in from.search(query)? {
+        let Some((service, user)) = source.get_specifiers() else {
+            report.entries.push(MigratedEntry {
+                service: String::new(),
+                user: String::new(),
+                outcome: MigrationOutcome::Failed("credential has no service and user".into()),
+                warnings: Vec::new(),
+            });
+            continue;
+        };
+        let mut warnings = Vec::new();
+        let outcome = migrate_entry(&source, to, &service, &user, options, &mut warnings)
+            .unwrap_or_else(|err| MigrationOutcome::Failed(err.to_string()));
+        report.entries.push(MigratedEntry {
+            service,
+            user,
+            outcome,
+            warnings,
+        });
+    }
+    Ok(report)
+}
+
+fn migrate_entry(
+    source: &Entry,
+    to: &Arc<CredentialStore>,
+    service: &str,
+    user: &str,
+    options: &MigrateOptions,
+    warnings: &mut Vec<String>,
+) -> Result<MigrationOutcome> {
+    let secret = source.get_secret()?;
+    let attributes = source.get_attributes().unwrap_or_else(|err| {
+        warnings.push(format!("couldn't read attributes: {err}"));
+        HashMap::new()
+    });
+    let target = to.build(service, user, None)?;
+    let outcome = copy_credential(
+        &target,
+        &secret,
+        &attributes,
+        options.on_conflict,
+        options.dry_run,
+        warnings,
+    )?;
+    if options.delete_source
+        && !options.dry_run
+        && outcome != MigrationOutcome::Skipped
+        && let Err(err) = source.delete_credential()
+    {
+        warnings.push(format!("couldn't delete from the source store: {err}"));
+    }
+    Ok(outcome)
+}
+
+/// Write a secret and its attributes to a target entry, honoring the conflict policy.
+///
+/// Does the conflict check but writes nothing in a dry run.
+pub(super) fn copy_credential(
+    target: &Entry,
+    secret: &[u8],
+    attributes: &HashMap<String, String>,
+    on_conflict: ConflictPolicy,
+    dry_run: bool,
+    warnings: &mut Vec<String>,
+) -> Result<MigrationOutcome> {
+    let exists = match target.get_secret() {
+        Ok(_) | Err(Error::Ambiguous(_)) => true,
+        Err(Error::NoEntry) => false,
+        Err(err) => return Err(err),
+    };
+    let outcome = match (exists, on_conflict) {
+        (false, _) => MigrationOutcome::Copied,
+        (true, ConflictPolicy::Overwrite) => MigrationOutcome::Overwritten,
+        (true, ConflictPolicy::Skip) => MigrationOutcome::Skipped,
+    };
+    if dry_run || outcome == MigrationOutcome::Skipped {
+        return Ok(outcome);
+    }
+    target.set_secret(secret)?;
+    copy_attributes(target, attributes, warnings);
+    Ok(outcome)
+}
+
+/// Copy attributes to a target credential.
+///
+/// Stores differ in which attributes they allow to be updated, so if the target
+/// won't take all the attributes at once they are copied one at a time. Attributes
+/// that are rejected and that the target maintains for itself (such as a `uuid`)
+/// are silently left alone; other rejected attributes produce a warning.
+fn copy_attributes(
+    target: &Entry,
+    attributes: &HashMap<String, String>,
+    warnings: &mut Vec<String>,
+) {
+    if attributes.is_empty()
+        || target
+            .update_attributes(&internalize(Some(attributes)))
+            .is_ok()
+    {
+        return;
+    }
+    let own = target.get_attributes().unwrap_or_default();
+    let mut rejected: Vec<&str> = attributes
+        .iter()
+        .filter(|(key, val)| {
+            let single = HashMap::from([(key.as_str(), val.as_str())]);
+            target.update_attributes(&single).is_err() && !own.contains_key(*key)
+        })
+        .map(|(key, _)| key.as_str())
+        .collect();
+    if !rejected.is_empty() {
+        rejected.sort();
+        warnings.push(format!("couldn't copy attributes: {}", rejected.join(", ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    fn stores() -> (Arc<CredentialStore>, Arc<CredentialStore>) {
+        let from = build_sample_store(&HashMap::new()).unwrap();
+        let to = build_sample_store(&HashMap::new()).unwrap();
+        for (service, user) in [
+            ("migrate-a", "u1"),
+            ("migrate-a", "u2"),
+            ("migrate-b", "u1"),
+        ] {
+            let entry = new_entry_in(&from, service, user).unwrap();
+            entry.set_password(&format!("{service}/{user}")).unwrap();
+        }
+        new_entry_in(&to, "migrate-a", "u2")
+            .unwrap()
+            .set_password("existing")
+            .unwrap();
+        (from, to)
+    }
+
+    fn outcome_of(report: &MigrationReport, user: &str) -> MigrationOutcome {
+        let entry = report.entries.iter().find(|e| e.user == user).unwrap();
+        entry.outcome.clone()
+    }
+
+    #[test]
+    fn test_migrate_skip_and_dry_run() {
+        let (from, to) = stores();
+        let query = HashMap::from([("service", "migrate-a")]);
+        let options = MigrateOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = migrate_between(&from, &to, &query, &options).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.entries.len(), 2);
+        assert_eq!(outcome_of(&report, "u1"), MigrationOutcome::Copied);
+        assert_eq!(outcome_of(&report, "u2"), MigrationOutcome::Skipped);
+        let copy = new_entry_in(&to, "migrate-a", "u1").unwrap();
+        assert!(matches!(copy.get_password(), Err(Error::NoEntry)));
+        let report = migrate_between(&from, &to, &query, &MigrateOptions::default()).unwrap();
+        assert_eq!(report.failures(), 0);
+        assert_eq!(copy.get_password().unwrap(), "migrate-a/u1");
+        let existing = new_entry_in(&to, "migrate-a", "u2").unwrap();
+        assert_eq!(existing.get_password().unwrap(), "existing");
+    }
+
+    #[test]
+    fn test_migrate_overwrite_and_delete() {
+        let (from, to) = stores();
+        let source = new_entry_in(&from, "migrate-a", "u2").unwrap();
+        source
+            .update_attributes(&HashMap::from([("comment", "copied")]))
+            .unwrap();
+        let options = MigrateOptions {
+            on_conflict: ConflictPolicy::Overwrite,
+            delete_source: true,
+            ..Default::default()
+        };
+        let report = migrate_between(&from, &to, &HashMap::new(), &options).unwrap();
+        assert_eq!(report.entries.len(), 3);
+        assert_eq!(outcome_of(&report, "u2"), MigrationOutcome::Overwritten);
+        let existing = new_entry_in(&to, "migrate-a", "u2").unwrap();
+        assert_eq!(existing.get_password().unwrap(), "migrate-a/u2");
+        assert_eq!(existing.get_attributes().unwrap()["comment"], "copied");
+        assert!(report.entries.iter().all(|e| e.warnings.is_empty()));
+        assert!(from.search(&HashMap::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_same_store() {
+        let query = HashMap::new();
+        let options = MigrateOptions::default();
+        assert!(matches!(
+            migrate("sample", "sample", &query, &options),
+            Err(Error::Invalid(_, _))
+        ));
+        for (from, to) in [
+            ("Dir", "dir:root=/run/secrets,layout=flat"),
+            (
+                "sample:persist=false,cache_ttl_secs=5",
+                "sample:cache-ttl-secs=5,persist=false",
+            ),
+        ] {
+            assert!(
+                matches!(
+                    migrate(from, to, &query, &options),
+                    Err(Error::Invalid(_, _))
+                ),
+                "{from} and {to} were treated as different stores"
+            );
+        }
+    }
+}
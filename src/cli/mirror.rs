@@ -0,0 +1,560 @@
+//! A composite store that mirrors every change to several stores.
+//!
+//! This is meant for keeping a backup copy of credentials: every write or
+//! deletion is applied to all the mirrored stores, while reads come from
+//! the first store that can satisfy them. It is registered under the name
+//! `mirror`, and takes these modifiers:
+//!
+//! - `stores` (required): the names of the mirrored stores, separated by `+`,
+//!   e.g., `stores=keyutils+sqlite`. No store may be named twice.
+//! - `policy`: what to do when a change fails in some but not all stores
+//!   (see [MirrorPolicy]). One of `fail` (the default), `best-effort`, or `rollback`.
+//!
+//! Any modifier of the form `name.key=val`, where `name` is one of the mirrored
+//! stores, is passed (as `key=val`) to that store's builder. So, for example,
+//! `mirror:stores=keyutils+sqlite,sqlite.path=backup.db,policy=rollback`
+//! mirrors the keyutils store to an sqlite database in `backup.db`.
+//!
+//! Searches are done in the first store that can do them.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+use super::build_named_store_with_modifiers;
+
+/// What a [MirrorStore] does when a change succeeds in some stores but fails in another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MirrorPolicy {
+    /// Stop at the first store that fails and return its error.
+    /// Stores earlier in the list keep the change.
+    Fail,
+    /// Apply the change to every store, and succeed if any of them succeeded.
+    /// If all of them fail, the first error is returned.
+    BestEffort,
+    /// Stop at the first store that fails, undo the change in the stores
+    /// earlier in the list, and return the error.
+    ///
+    /// Undoing an update of attributes puts back their old values. Since
+    /// attributes can't be removed from a credential, any that it didn't have
+    /// before are set to the empty string. If the store doesn't support updating
+    /// them, the credential is instead re-created with its old secret and
+    /// attributes (other than those, such as creation dates, that can't be set).
+    Rollback,
+}
+
+/// A store that mirrors changes to several stores. See the module docs.
+pub struct MirrorStore {
+    stores: Vec<Arc<CredentialStore>>,
+    policy: MirrorPolicy,
+}
+
+impl std::fmt::Debug for MirrorStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MirrorStore")
+            .field("stores", &self.stores)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl MirrorStore {
+    /// Create a mirror store from existing stores.
+    ///
+    /// Gives an `Invalid` error if no stores are given.
+    pub fn new(stores: Vec<Arc<CredentialStore>>, policy: MirrorPolicy) -> Result<Arc<Self>> {
+        if stores.is_empty() {
+            return Err(invalid("stores", "must list at least one store"));
+        }
+        Ok(Arc::new(MirrorStore { stores, policy }))
+    }
+
+    /// Create a mirror store from modifiers, as described in the module docs.
+    pub fn new_with_modifiers(modifiers: &HashMap<&str, &str>) -> Result<Arc<Self>> {
+        let names: Vec<&str> = match modifiers.get("stores") {
+            Some(names) => names.split('+').collect(),
+            None => return Err(invalid("stores", "must be specified")),
+        };
+        for (i, name) in names.iter().enumerate() {
+            if name.is_empty() || names[..i].contains(name) {
+                return Err(invalid("stores", "must list distinct store names"));
+            }
+        }
+        let policy = match modifiers.get("policy").copied() {
+            None | Some("fail") => MirrorPolicy::Fail,
+            Some("best-effort") => MirrorPolicy::BestEffort,
+            Some("rollback") => MirrorPolicy::Rollback,
+            Some(_) => return Err(invalid("policy", "must be fail, best-effort, or rollback")),
+        };
+        let mut inner_mods: Vec<HashMap<&str, &str>> = vec![HashMap::new(); names.len()];
+        for (key, value) in modifiers {
+            if *key == "stores" || *key == "policy" {
+                continue;
+            }
+            let position = key
+                .split_once('.')
+                .and_then(|(name, _)| names.iter().position(|n| *n == name));
+            match position {
+                Some(i) => {
+                    inner_mods[i].insert(&key[names[i].len() + 1..], *value);
+                }
+                None => return Err(invalid(key, "unknown key")),
+            }
+        }
+        let stores = names
+            .iter()
+            .zip(inner_mods.iter())
+            .map(|(name, mods)| build_named_store_with_modifiers(name, mods))
+            .collect::<Result<Vec<_>>>()?;
+        Self::new(stores, policy)
+    }
+}
+
+fn invalid(key: &str, reason: &str) -> Error {
+    Error::Invalid(key.to_string(), reason.to_string())
+}
+
+impl CredentialStoreApi for MirrorStore {
+    /// See the API docs.
+    fn vendor(&self) -> String {
+        String::from("Mirror store, https://crates.io/crates/keyring")
+    }
+
+    /// See the API docs.
+    ///
+    /// The id combines the ids of the mirrored stores.
+    fn id(&self) -> String {
+        let ids: Vec<String> = self.stores.iter().map(|s| s.id()).collect();
+        format!("Mirror: {}", ids.join("; "))
+    }
+
+    /// See the API docs.
+    ///
+    /// Any modifiers are passed to all the mirrored stores.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let entries = self
+            .stores
+            .iter()
+            .map(|s| s.build(service, user, mods).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Entry::new_with_credential(Arc::new(MirrorCredential {
+            entries,
+            policy: self.policy,
+        })))
+    }
+
+    /// See the API docs.
+    ///
+    /// The results come from the first store that can do the search.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        first_success(&self.stores, |s| s.search(spec))
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the persistence of the first mirrored store.
+    fn persistence(&self) -> CredentialPersistence {
+        self.stores[0].persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Return the result of the first successful operation, or the first error if none succeed.
+fn first_success<T, R>(items: &[T], op: impl Fn(&T) -> Result<R>) -> Result<R> {
+    let mut first_err = None;
+    for item in items {
+        match op(item) {
+            Ok(result) => return Ok(result),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    Err(first_err.unwrap_or(Error::NoEntry))
+}
+
+/// A credential in a [MirrorStore], with one entry per mirrored store.
+#[derive(Debug)]
+struct MirrorCredential {
+    entries: Vec<Arc<Entry>>,
+    policy: MirrorPolicy,
+}
+
+impl MirrorCredential {
+    /// Apply a change to every entry according to the policy.
+    ///
+    /// For rollbacks, `save` captures an entry's state before the change,
+    /// and `restore` puts it back.
+    fn fan_out<S>(
+        &self,
+        change: impl Fn(&Entry) -> Result<()>,
+        save: impl Fn(&Entry) -> Result<S>,
+        restore: impl Fn(&Entry, S) -> Result<()>,
+    ) -> Result<()> {
+        match self.policy {
+            MirrorPolicy::Fail => {
+                for entry in &self.entries {
+                    change(entry)?;
+                }
+                Ok(())
+            }
+            MirrorPolicy::BestEffort => {
+                let mut first_err = None;
+                let mut succeeded = false;
+                for entry in &self.entries {
+                    match change(entry) {
+                        Ok(()) => succeeded = true,
+                        Err(err) => {
+                            first_err.get_or_insert(err);
+                        }
+                    }
+                }
+                match first_err {
+                    Some(err) if !succeeded => Err(err),
+                    _ => Ok(()),
+                }
+            }
+            MirrorPolicy::Rollback => {
+                let mut saved = Vec::new();
+                for entry in &self.entries {
+                    let result = save(entry).and_then(|state| {
+                        change(entry)?;
+                        Ok(state)
+                    });
+                    match result {
+                        Ok(state) => saved.push((entry.as_ref(), state)),
+                        Err(err) => {
+                            // undo in reverse order; errors in undoing can't be reported
+                            for (entry, state) in saved.into_iter().rev() {
+                                let _ = restore(entry, state);
+                            }
+                            return Err(err);
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Capture an entry's secret, if it has one.
+fn save_secret(entry: &Entry) -> Result<Option<Vec<u8>>> {
+    match entry.get_secret() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(Error::NoEntry) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Put back a captured secret, deleting the credential if there wasn't one.
+fn restore_secret(entry: &Entry, secret: Option<Vec<u8>>) -> Result<()> {
+    match secret {
+        Some(secret) => entry.set_secret(&secret),
+        None => entry.delete_credential(),
+    }
+}
+
+/// Undo an update of the given attributes, putting back the saved ones.
+///
+/// Because attributes can't be removed, those the credential didn't have
+/// before are set to the empty string. If the store doesn't support that
+/// update, the credential is re-created with its saved secret, and then given
+/// back whichever of its saved attributes its store allows to be set.
+fn restore_attributes(
+    entry: &Entry,
+    attributes: &HashMap<&str, &str>,
+    saved: HashMap<String, String>,
+    secret: Vec<u8>,
+) -> Result<()> {
+    let previous: HashMap<&str, &str> = attributes
+        .keys()
+        .map(|key| (*key, saved.get(*key).map_or("", String::as_str)))
+        .collect();
+    match entry.update_attributes(&previous) {
+        Err(Error::NotSupportedByStore(_)) => {}
+        result => return result,
+    }
+    entry.delete_credential()?;
+    entry.set_secret(&secret)?;
+    for (key, value) in &saved {
+        // some attributes (such as creation dates) can't be set, which is fine
+        let _ = entry.update_attributes(&HashMap::from([(key.as_str(), value.as_str())]));
+    }
+    Ok(())
+}
+
+impl CredentialApi for MirrorCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.fan_out(|e| e.set_secret(secret), save_secret, restore_secret)
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        first_success(&self.entries, |e| e.get_secret())
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        first_success(&self.entries, |e| e.get_attributes())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.fan_out(
+            |e| e.update_attributes(attributes),
+            |e| Ok((e.get_attributes()?, e.get_secret()?)),
+            |e, (saved, secret)| restore_attributes(e, attributes, saved, secret),
+        )
+    }
+
+    /// Deletes the credential from every store that has it.
+    ///
+    /// This succeeds if any of the stores had a credential.
+    fn delete_credential(&self) -> Result<()> {
+        let found = std::cell::Cell::new(false);
+        let delete = |e: &Entry| match e.delete_credential() {
+            Ok(()) => {
+                found.set(true);
+                Ok(())
+            }
+            Err(Error::NoEntry) => Ok(()),
+            Err(err) => Err(err),
+        };
+        self.fan_out(delete, save_secret, restore_secret)?;
+        if found.get() {
+            Ok(())
+        } else {
+            Err(Error::NoEntry)
+        }
+    }
+
+    /// Returns a wrapper for the credentials that exist in any of the mirrored stores.
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let mut entries = Vec::new();
+        let mut first_err = None;
+        for entry in &self.entries {
+            match entry.get_credential() {
+                Ok(found) => entries.push(Arc::new(found)),
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        if entries.is_empty() {
+            return Err(first_err.unwrap_or(Error::NoEntry));
+        }
+        Ok(Some(Arc::new(MirrorCredential {
+            entries,
+            policy: self.policy,
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.entries.first().and_then(|e| e.get_specifiers())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    /// A store whose credentials can never be written, and can be read
+    /// only if the store isn't locked.
+    #[derive(Debug)]
+    struct BrokenStore {
+        locked: bool,
+    }
+
+    #[derive(Debug)]
+    struct BrokenCredential {
+        locked: bool,
+    }
+
+    impl CredentialStoreApi for BrokenStore {
+        fn vendor(&self) -> String {
+            "broken".to_string()
+        }
+        fn id(&self) -> String {
+            "broken".to_string()
+        }
+        fn build(&self, _: &str, _: &str, _: Option<&HashMap<&str, &str>>) -> Result<Entry> {
+            Ok(Entry::new_with_credential(Arc::new(BrokenCredential {
+                locked: self.locked,
+            })))
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialApi for BrokenCredential {
+        fn set_secret(&self, _: &[u8]) -> Result<()> {
+            Err(Error::NotSupportedByStore("broken".to_string()))
+        }
+        fn get_secret(&self) -> Result<Vec<u8>> {
+            if self.locked {
+                Err(Error::NoStorageAccess("locked".into()))
+            } else {
+                Err(Error::NoEntry)
+            }
+        }
+        fn delete_credential(&self) -> Result<()> {
+            Err(Error::NotSupportedByStore("broken".to_string()))
+        }
+        fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+            Err(Error::NoEntry)
+        }
+        fn get_specifiers(&self) -> Option<(String, String)> {
+            None
+        }
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn mirror(policy: MirrorPolicy) -> (Arc<CredentialStore>, Arc<CredentialStore>) {
+        mirror_to(policy, false)
+    }
+
+    fn mirror_to(
+        policy: MirrorPolicy,
+        locked: bool,
+    ) -> (Arc<CredentialStore>, Arc<CredentialStore>) {
+        let sample = build_sample_store(&HashMap::new()).unwrap();
+        let broken: Arc<CredentialStore> = Arc::new(BrokenStore { locked });
+        let mirror = MirrorStore::new(vec![sample.clone(), broken], policy).unwrap();
+        (mirror, sample)
+    }
+
+    #[test]
+    fn test_mirrored_writes() {
+        let (a, b) = (
+            build_sample_store(&HashMap::new()).unwrap(),
+            build_sample_store(&HashMap::new()).unwrap(),
+        );
+        let mirror: Arc<CredentialStore> =
+            MirrorStore::new(vec![a.clone(), b.clone()], MirrorPolicy::Fail).unwrap();
+        let entry = new_entry_in(&mirror, "svc", "usr").unwrap();
+        entry.set_password("mirrored").unwrap();
+        let in_b = new_entry_in(&b, "svc", "usr").unwrap();
+        assert_eq!(in_b.get_password().unwrap(), "mirrored");
+        new_entry_in(&a, "svc", "usr")
+            .unwrap()
+            .delete_credential()
+            .unwrap();
+        assert_eq!(entry.get_password().unwrap(), "mirrored");
+        entry.delete_credential().unwrap();
+        assert!(matches!(in_b.get_password(), Err(Error::NoEntry)));
+        assert!(matches!(entry.delete_credential(), Err(Error::NoEntry)));
+    }
+
+    #[test]
+    fn test_policies() {
+        let (store, sample) = mirror(MirrorPolicy::Fail);
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert!(entry.set_password("fail").is_err());
+        let in_sample = new_entry_in(&sample, "svc", "usr").unwrap();
+        assert_eq!(in_sample.get_password().unwrap(), "fail");
+
+        let (store, sample) = mirror(MirrorPolicy::BestEffort);
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        entry.set_password("best-effort").unwrap();
+        let in_sample = new_entry_in(&sample, "svc", "usr").unwrap();
+        assert_eq!(in_sample.get_password().unwrap(), "best-effort");
+
+        let (store, sample) = mirror(MirrorPolicy::Rollback);
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert!(entry.set_password("rollback").is_err());
+        let in_sample = new_entry_in(&sample, "svc", "usr").unwrap();
+        assert!(matches!(in_sample.get_password(), Err(Error::NoEntry)));
+        in_sample.set_password("original").unwrap();
+        assert!(entry.set_password("rollback").is_err());
+        assert_eq!(in_sample.get_password().unwrap(), "original");
+        assert!(entry.delete_credential().is_err());
+        assert_eq!(in_sample.get_password().unwrap(), "original");
+        // attributes that weren't there before are cleared, in place
+        let uuid = in_sample.get_attributes().unwrap()["uuid"].clone();
+        assert!(
+            entry
+                .update_attributes(&HashMap::from([("comment", "added")]))
+                .is_err()
+        );
+        assert_eq!(in_sample.get_password().unwrap(), "original");
+        let attributes = in_sample.get_attributes().unwrap();
+        assert_eq!(attributes["comment"], "");
+        assert_eq!(attributes["uuid"], uuid);
+    }
+
+    #[test]
+    fn test_rollback_when_saving_fails() {
+        let (store, sample) = mirror_to(MirrorPolicy::Rollback, true);
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert!(matches!(
+            entry.set_password("rollback"),
+            Err(Error::NoStorageAccess(_))
+        ));
+        let in_sample = new_entry_in(&sample, "svc", "usr").unwrap();
+        assert!(matches!(in_sample.get_password(), Err(Error::NoEntry)));
+        in_sample.set_password("original").unwrap();
+        assert!(matches!(
+            entry.set_password("rollback"),
+            Err(Error::NoStorageAccess(_))
+        ));
+        assert_eq!(in_sample.get_password().unwrap(), "original");
+    }
+
+    #[test]
+    fn test_modifiers() {
+        let mods = HashMap::from([
+            ("stores", "sample+layered"),
+            ("sample.persist", "false"),
+            ("layered.primary", "sample"),
+            ("layered.primary.persist", "false"),
+            ("layered.secondary", "sample"),
+            ("layered.secondary.persist", "false"),
+            ("policy", "best-effort"),
+        ]);
+        let store = MirrorStore::new_with_modifiers(&mods).unwrap();
+        assert_eq!(store.stores.len(), 2);
+        assert_eq!(store.policy, MirrorPolicy::BestEffort);
+        for (key, mods) in [
+            ("stores", HashMap::new()),
+            ("stores", HashMap::from([("stores", "sample+sample")])),
+            (
+                "policy",
+                HashMap::from([("stores", "sample"), ("policy", "maybe")]),
+            ),
+            (
+                "sqlite.path",
+                HashMap::from([("stores", "sample"), ("sqlite.path", "x")]),
+            ),
+        ] {
+            match MirrorStore::new_with_modifiers(&mods) {
+                Err(Error::Invalid(bad, _)) => assert_eq!(bad, key),
+                other => panic!("Bad modifiers {mods:?} gave {other:?}"),
+            }
+        }
+    }
+}
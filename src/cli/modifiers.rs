@@ -0,0 +1,617 @@
+//! Descriptions of the modifiers accepted by named stores.
+//!
+//! Each store in the registry can declare the modifiers it accepts (see
+//! [store_modifiers](super::store_modifiers)). Modifiers given to a store
+//! with a declared schema are checked against it before the store is built,
+//! so that a typo such as `persit=true` is reported as such rather than being
+//! ignored or producing some less helpful error from the store itself.
+//!
+//! Besides its own modifiers, every named store accepts the modifiers listed by
+//! [wrapper_modifiers], which wrap the built store in another store that adds
+//! some behavior (such as caching) to it. Each of these can be given either in
+//! kebab case (`cache-ttl-secs`) or in snake case (`cache_ttl_secs`).
+
+use std::collections::HashMap;
+
+use keyring_core::{Error, Result};
+
+/// The type of value a modifier takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModifierKind {
+    /// Any string.
+    String,
+    /// `true` or `false`.
+    Bool,
+    /// A non-negative integer.
+    Integer,
+    /// One of a fixed set of values (which are case-sensitive).
+    Choice(Vec<String>),
+}
+
+impl std::fmt::Display for ModifierKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModifierKind::String => write!(f, "string"),
+            ModifierKind::Bool => write!(f, "bool"),
+            ModifierKind::Integer => write!(f, "integer"),
+            ModifierKind::Choice(choices) => write!(f, "{}", choices.join("|")),
+        }
+    }
+}
+
+/// The description of one modifier accepted by a store.
+///
+/// A key can contain a placeholder in angle brackets, which stands for any
+/// non-empty text. For example, the layered store accepts `primary.<key>`,
+/// which matches `primary.persist` and `primary.backing-file`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModifierSpec {
+    pub key: String,
+    pub kind: ModifierKind,
+    /// The value used when the modifier isn't given, if there is one.
+    pub default: Option<String>,
+    pub description: String,
+}
+
+impl ModifierSpec {
+    /// Whether the given modifier key is described by this spec.
+    pub fn matches(&self, key: &str) -> bool {
+        pattern_matches(&self.key, key)
+    }
+
+    /// Check a value against this spec's kind.
+    ///
+    /// Gives an `Invalid` error naming the key if the value is not acceptable.
+    pub fn check_value(&self, key: &str, value: &str) -> Result<()> {
+        let ok = match &self.kind {
+            ModifierKind::String => true,
+            ModifierKind::Bool => value == "true" || value == "false",
+            ModifierKind::Integer => value.parse::<u64>().is_ok(),
+            ModifierKind::Choice(choices) => choices.iter().any(|c| c == value),
+        };
+        if ok {
+            return Ok(());
+        }
+        let expected = match &self.kind {
+            ModifierKind::Bool => "must be true or false".to_string(),
+            ModifierKind::Integer => "must be a non-negative integer".to_string(),
+            ModifierKind::Choice(choices) => format!("must be one of: {}", choices.join(", ")),
+            ModifierKind::String => unreachable!(),
+        };
+        Err(Error::Invalid(key.to_string(), expected))
+    }
+}
+
+fn pattern_matches(pattern: &str, key: &str) -> bool {
+    let Some(start) = pattern.find('<') else {
+        return pattern == key;
+    };
+    let Some(end) = pattern[start..].find('>').map(|i| start + i + 1) else {
+        return pattern == key;
+    };
+    let (prefix, rest) = (&pattern[..start], &pattern[end..]);
+    let Some(tail) = key.strip_prefix(prefix) else {
+        return false;
+    };
+    // the placeholder takes at least one character
+    tail.char_indices()
+        .skip(1)
+        .map(|(i, _)| i)
+        .chain(std::iter::once(tail.len()))
+        .filter(|&i| i > 0)
+        .any(|i| pattern_matches(rest, &tail[i..]))
+}
+
+/// Check modifiers against a store's schema.
+///
+/// Gives an `Invalid` error for the first unknown key or unacceptable value.
+/// The error for an unknown key lists the keys the store accepts, and suggests
+/// the one that was probably meant if the key looks like a typo.
+pub(super) fn check_modifiers(
+    store: &str,
+    specs: &[ModifierSpec],
+    modifiers: &HashMap<&str, &str>,
+) -> Result<()> {
+    let mut keys: Vec<&&str> = modifiers.keys().collect();
+    keys.sort();
+    for key in keys {
+        match specs.iter().find(|spec| spec.matches(key)) {
+            Some(spec) => spec.check_value(key, modifiers[key])?,
+            None => return Err(unknown_key(store, specs, key)),
+        }
+    }
+    Ok(())
+}
+
+fn unknown_key(store: &str, specs: &[ModifierSpec], key: &str) -> Error {
+    if specs.is_empty() {
+        let reason = format!("is not a modifier: the {store} store takes no modifiers");
+        return Error::Invalid(key.to_string(), reason);
+    }
+    let known: Vec<&str> = specs.iter().map(|s| s.key.as_str()).collect();
+    let mut reason = format!(
+        "is not a modifier of the {store} store, which accepts: {}",
+        known.join(", ")
+    );
+    let closest = known
+        .iter()
+        .map(|k| (edit_distance(key, k), k))
+        .min_by_key(|(distance, _)| *distance);
+    if let Some((distance, suggestion)) = closest
+        && distance <= 2
+    {
+        reason = format!("{reason} (did you mean '{suggestion}'?)");
+    }
+    Error::Invalid(key.to_string(), reason)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn spec(key: &str, kind: ModifierKind, default: Option<&str>, description: &str) -> ModifierSpec {
+    ModifierSpec {
+        key: key.to_string(),
+        kind,
+        default: default.map(str::to_string),
+        description: description.to_string(),
+    }
+}
+
+fn choice(choices: &[&str]) -> ModifierKind {
+    ModifierKind::Choice(choices.iter().map(|c| c.to_string()).collect())
+}
+
+/// The modifiers that wrap a store, which are accepted by every named store.
+pub(super) fn wrapper_modifiers() -> Vec<ModifierSpec> {
+    use ModifierKind::{Bool, Integer};
+
+    let kebab = vec![
+        spec(
+            "audit-log",
+            ModifierKind::String,
+            None,
+            "Append a record of every operation on the store to this file.",
+        ),
+        spec(
+            "timeout-ms",
+            Integer,
+            None,
+            "Give up on operations that take longer than this (0 for never).",
+        ),
+        spec(
+            "retry-attempts",
+            Integer,
+            None,
+            "Try failed operations up to this many times in all.",
+        ),
+        spec(
+            "retry-backoff-ms",
+            Integer,
+            Some("100"),
+            "Wait this long before the first retry, doubling it for each retry.",
+        ),
+        spec(
+            "retry-max-backoff-ms",
+            Integer,
+            Some("5000"),
+            "Never wait longer than this between retries.",
+        ),
+        spec(
+            "retry-on",
+            ModifierKind::String,
+            Some("PlatformFailure+NoStorageAccess"),
+            "Retry errors of these variants (separated by +).",
+        ),
+        spec(
+            "cache-ttl-secs",
+            Integer,
+            None,
+            "Cache secrets read from the store for this many seconds.",
+        ),
+        spec(
+            "cache-max-entries",
+            Integer,
+            Some("256"),
+            "The most secrets to cache (with cache-ttl-secs).",
+        ),
+        spec(
+            "namespace",
+            ModifierKind::String,
+            None,
+            "Keep the store's entries in this namespace of the store.",
+        ),
+        spec(
+            "namespace-separator",
+            ModifierKind::String,
+            Some(":"),
+            "Put this between the namespace and service names (with namespace).",
+        ),
+        spec(
+            "read-only",
+            Bool,
+            Some("false"),
+            "Refuse to set, update, or delete credentials.",
+        ),
+    ];
+    let snake = kebab.iter().map(|m| ModifierSpec {
+        key: m.key.replace('-', "_"),
+        description: format!("Same as {}.", m.key),
+        ..m.clone()
+    });
+    let snake: Vec<ModifierSpec> = snake.collect();
+    kebab.into_iter().chain(snake).collect()
+}
+
+/// Split modifiers into those that wrap the store and those for the store's builder.
+pub(super) fn split_wrapper_modifiers<'a>(
+    modifiers: &HashMap<&'a str, &'a str>,
+) -> (HashMap<&'a str, &'a str>, HashMap<&'a str, &'a str>) {
+    let wrappers = wrapper_modifiers();
+    modifiers
+        .iter()
+        .map(|(k, v)| (*k, *v))
+        .partition(|(key, _)| wrappers.iter().any(|spec| spec.matches(key)))
+}
+
+/// Get the value of a wrapper modifier, given its kebab-case key.
+pub(super) fn wrapper_setting<'a>(
+    modifiers: &HashMap<&str, &'a str>,
+    key: &str,
+) -> Option<&'a str> {
+    modifiers
+        .get(key)
+        .or_else(|| modifiers.get(key.replace('-', "_").as_str()))
+        .copied()
+}
+
+/// Get the value of a numeric wrapper modifier, given its kebab-case key.
+pub(super) fn wrapper_number(modifiers: &HashMap<&str, &str>, key: &str) -> Result<Option<u64>> {
+    match wrapper_setting(modifiers, key) {
+        Some(value) => match value.parse() {
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(Error::Invalid(
+                key.to_string(),
+                "must be a non-negative integer".to_string(),
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The modifiers accepted by each of the built-in stores.
+pub(super) fn builtin_modifiers(name: &str) -> Vec<ModifierSpec> {
+    use ModifierKind::{Bool, String};
+
+    match name {
+        "android" => vec![
+            spec(
+                "name",
+                String,
+                Some("default"),
+                "The name of the vault to use.",
+            ),
+            spec(
+                "filename",
+                String,
+                Some("keyring-<name>"),
+                "The shared preferences file holding the vault.",
+            ),
+            spec(
+                "divider",
+                String,
+                Some("\u{FEFF}@\u{FEFF}"),
+                "The divider between service and user in preference keys.",
+            ),
+        ],
+        "dir" => vec![
+            spec(
+                "root",
+                String,
+                Some("/run/secrets"),
+                "The directory holding the credential files.",
+            ),
+            spec(
+                "layout",
+                choice(&["flat", "nested"]),
+                Some("flat"),
+                "Whether files are named <service>.<user> or <service>/<user>.",
+            ),
+        ],
+        "env" => vec![
+            spec(
+                "template",
+                String,
+                Some("KEYRING_{SERVICE}_{USER}"),
+                "The name of a credential's variable.",
+            ),
+            spec(
+                "normalize",
+                Bool,
+                Some("true"),
+                "Upper-case names and replace punctuation with _ in variable names.",
+            ),
+            spec(
+                "writable",
+                Bool,
+                Some("false"),
+                "Allow changes, in an in-process overlay of the environment.",
+            ),
+        ],
+        "exec" => vec![
+            spec("command", String, None, "The helper program (required)."),
+            spec(
+                "args",
+                String,
+                None,
+                "Space-separated arguments given to the helper before the operation.",
+            ),
+        ],
+        "kdbx" => vec![
+            spec("path", String, None, "The KDBX4 database file (required)."),
+            spec("password", String, None, "The database's master password."),
+            spec(
+                "keyfile",
+                String,
+                None,
+                "The path of the database's key file.",
+            ),
+        ],
+        "keychain" => vec![spec(
+            "keychain",
+            choice(&["user", "system", "common", "dynamic"]),
+            Some("user"),
+            "The keychain domain to keep credentials in.",
+        )],
+        "keyutils" => vec![
+            spec(
+                "prefix",
+                String,
+                Some("keyring:"),
+                "The prefix of key descriptions.",
+            ),
+            spec(
+                "divider",
+                String,
+                Some("@"),
+                "The divider between user and service.",
+            ),
+            spec(
+                "suffix",
+                String,
+                Some(""),
+                "The suffix of key descriptions.",
+            ),
+            spec(
+                "service_no_divider",
+                Bool,
+                Some("false"),
+                "Reject service names that contain the divider.",
+            ),
+        ],
+        "protected" => vec![
+            spec(
+                "access-group",
+                String,
+                None,
+                "The access group to keep credentials in (default: the app's group).",
+            ),
+            spec(
+                "cloud-sync",
+                Bool,
+                Some("false"),
+                "Synchronize credentials with iCloud.",
+            ),
+        ],
+        "sample" => vec![
+            spec(
+                "backing-file",
+                String,
+                None,
+                "The file to load credentials from and save them to.",
+            ),
+            spec(
+                "persist",
+                Bool,
+                Some("true"),
+                "Save credentials to a file in the temporary directory.",
+            ),
+        ],
+        "secret-service" | "dbus-secret-service" => vec![],
+        "systemd-creds" => vec![spec(
+            "directory",
+            String,
+            Some("$CREDENTIALS_DIRECTORY"),
+            "The directory holding the credential files.",
+        )],
+        "sqlite" => vec![
+            spec(
+                "path",
+                String,
+                Some("$XDG_STATE_HOME/keystore.db"),
+                "The database file.",
+            ),
+            spec(
+                "encryption-cipher",
+                String,
+                None,
+                "The cipher to encrypt the database with (requires encryption-hexkey).",
+            ),
+            spec("cipher", String, None, "Same as encryption-cipher."),
+            spec(
+                "encryption-hexkey",
+                String,
+                None,
+                "The hex-encoded encryption key (requires encryption-cipher).",
+            ),
+            spec("hexkey", String, None, "Same as encryption-hexkey."),
+            spec(
+                "allow-ambiguity",
+                Bool,
+                Some("false"),
+                "Allow more than one credential per service and user.",
+            ),
+            spec(
+                "allow_ambiguity",
+                Bool,
+                Some("false"),
+                "Same as allow-ambiguity.",
+            ),
+            spec(
+                "vfs",
+                choice(&["memory", "io_uring", "syscall"]),
+                None,
+                "How the database is accessed.",
+            ),
+            spec(
+                "index-always",
+                Bool,
+                Some("false"),
+                "Index service and user even when ambiguity is allowed.",
+            ),
+            spec("index_always", Bool, Some("false"), "Same as index-always."),
+        ],
+        "windows" => vec![
+            spec(
+                "prefix",
+                String,
+                Some(""),
+                "The prefix of credential target names.",
+            ),
+            spec(
+                "divider",
+                String,
+                Some("."),
+                "The divider between user and service.",
+            ),
+            spec(
+                "suffix",
+                String,
+                Some(""),
+                "The suffix of credential target names.",
+            ),
+            spec(
+                "service_no_divider",
+                Bool,
+                Some("false"),
+                "Reject service names that contain the divider.",
+            ),
+        ],
+        "layered" => vec![
+            spec(
+                "primary",
+                String,
+                None,
+                "The name of the primary store (required).",
+            ),
+            spec(
+                "secondary",
+                String,
+                None,
+                "The name of the secondary store (required).",
+            ),
+            spec(
+                "read-order",
+                choice(&["primary-first", "secondary-first"]),
+                Some("primary-first"),
+                "Which store is read first.",
+            ),
+            spec(
+                "write-target",
+                choice(&["primary", "secondary", "both"]),
+                Some("primary"),
+                "Which stores are written.",
+            ),
+            spec(
+                "primary.<key>",
+                String,
+                None,
+                "A modifier for the primary store.",
+            ),
+            spec(
+                "secondary.<key>",
+                String,
+                None,
+                "A modifier for the secondary store.",
+            ),
+        ],
+        "mirror" => vec![
+            spec(
+                "stores",
+                String,
+                None,
+                "The names of the mirrored stores, separated by '+' (required).",
+            ),
+            spec(
+                "policy",
+                choice(&["fail", "best-effort", "rollback"]),
+                Some("fail"),
+                "What to do when a write to one of the stores fails.",
+            ),
+            spec(
+                "<store>.<key>",
+                String,
+                None,
+                "A modifier for one of the stores.",
+            ),
+        ],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        assert!(pattern_matches("persist", "persist"));
+        assert!(!pattern_matches("persist", "persist2"));
+        assert!(pattern_matches("primary.<key>", "primary.persist"));
+        assert!(pattern_matches("primary.<key>", "primary.primary.persist"));
+        assert!(!pattern_matches("primary.<key>", "primary."));
+        assert!(pattern_matches("<store>.<key>", "sample.persist"));
+        assert!(!pattern_matches("<store>.<key>", ".persist"));
+        assert!(!pattern_matches("<store>.<key>", "persist"));
+    }
+
+    #[test]
+    fn test_check_modifiers() {
+        let specs = builtin_modifiers("sample");
+        let persist = specs.iter().find(|spec| spec.key == "persist").unwrap();
+        assert_eq!(persist.default.as_deref(), Some("true"));
+        let ok = HashMap::from([("persist", "true")]);
+        assert!(check_modifiers("sample", &specs, &ok).is_ok());
+        match check_modifiers("sample", &specs, &HashMap::from([("persit", "true")])) {
+            Err(Error::Invalid(key, reason)) => {
+                assert_eq!(key, "persit");
+                assert!(reason.contains("did you mean 'persist'"), "{reason}");
+            }
+            other => panic!("Unknown key was accepted: {other:?}"),
+        }
+        assert!(check_modifiers("sample", &specs, &HashMap::from([("persist", "yes")])).is_err());
+        let specs = builtin_modifiers("keychain");
+        assert!(
+            check_modifiers("keychain", &specs, &HashMap::from([("keychain", "system")])).is_ok()
+        );
+        assert!(
+            check_modifiers("keychain", &specs, &HashMap::from([("keychain", "System")])).is_err()
+        );
+        let specs = builtin_modifiers("dir");
+        let nested = HashMap::from([("layout", "Nested")]);
+        assert!(check_modifiers("dir", &specs, &nested).is_err());
+        let specs = builtin_modifiers("secret-service");
+        assert!(check_modifiers("secret-service", &specs, &ok).is_err());
+    }
+}
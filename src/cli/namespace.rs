@@ -0,0 +1,213 @@
+//! A store wrapper that keeps its credentials in a namespace of another store.
+//!
+//! Applications that keep (say) staging and production credentials in the same
+//! store can wrap the store in a [NamespaceStore] for each environment. The
+//! wrapper prepends its namespace (and a separator, `:` by default) to the
+//! service name of every entry it builds, so `Entry::new("svc", "usr")` in the
+//! `staging` namespace refers to the credential with service `staging:svc`.
+//! The entries it returns report their service names without the namespace.
+//!
+//! Searches return only the credentials in the namespace: credentials outside
+//! it are invisible through the wrapper. If a search spec has a `service` value,
+//! the namespace is prepended to it before it is given to the wrapped store.
+//! (For stores whose searches take patterns, such as the sample store, this
+//! means that a pattern anchored to the start of the service name won't match.)
+//!
+//! Any named store can be wrapped by giving it the `namespace` modifier
+//! (and, optionally, `namespace-separator`).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// The separator put between a namespace and a service name by default.
+pub const DEFAULT_NAMESPACE_SEPARATOR: &str = ":";
+
+/// A store that confines its entries to a namespace of another store. See the module docs.
+#[derive(Debug)]
+pub struct NamespaceStore {
+    inner: Arc<CredentialStore>,
+    prefix: Arc<String>,
+}
+
+impl NamespaceStore {
+    /// Wrap a store so that its entries are confined to the given namespace.
+    ///
+    /// Service names in the namespace start with the namespace followed by the separator.
+    pub fn new(inner: Arc<CredentialStore>, namespace: &str, separator: &str) -> Arc<Self> {
+        Arc::new(NamespaceStore {
+            inner,
+            prefix: Arc::new(format!("{namespace}{separator}")),
+        })
+    }
+
+    /// The prefix that this store's service names have in the wrapped store.
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    fn wrap(&self, inner: Entry) -> Entry {
+        Entry::new_with_credential(Arc::new(NamespacedCredential {
+            inner,
+            prefix: self.prefix.clone(),
+        }))
+    }
+
+    fn contains(&self, entry: &Entry) -> bool {
+        match entry.get_specifiers() {
+            Some((service, _)) => service.starts_with(self.prefix.as_str()),
+            None => false,
+        }
+    }
+}
+
+impl CredentialStoreApi for NamespaceStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    ///
+    /// The service name is put in the namespace before the entry is built.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let service = format!("{}{service}", self.prefix);
+        Ok(self.wrap(self.inner.build(&service, user, mods)?))
+    }
+
+    /// See the API docs.
+    ///
+    /// Only credentials in the namespace are returned.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let service = spec.get("service").map(|s| format!("{}{s}", self.prefix));
+        let mut spec = spec.clone();
+        if let Some(service) = &service {
+            spec.insert("service", service);
+        }
+        let found = self.inner.search(&spec)?;
+        let found = found.into_iter().filter(|e| self.contains(e));
+        Ok(found.map(|e| self.wrap(e)).collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [NamespaceStore].
+#[derive(Debug)]
+struct NamespacedCredential {
+    inner: Entry,
+    prefix: Arc<String>,
+}
+
+impl CredentialApi for NamespacedCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.inner.set_secret(secret)
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.inner.get_secret()
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.inner.get_attributes()
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.inner.update_attributes(attributes)
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.inner.delete_credential()
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        Ok(Some(Arc::new(NamespacedCredential {
+            inner: self.inner.get_credential()?,
+            prefix: self.prefix.clone(),
+        })))
+    }
+
+    /// Returns the service name without the namespace.
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        let (service, user) = self.inner.get_specifiers()?;
+        match service.strip_prefix(self.prefix.as_str()) {
+            Some(service) => Some((service.to_string(), user)),
+            None => Some((service, user)),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    #[test]
+    fn test_namespace() {
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let staging: Arc<CredentialStore> = NamespaceStore::new(inner.clone(), "staging", ":");
+        let production: Arc<CredentialStore> = NamespaceStore::new(inner.clone(), "prod", ":");
+        let entry = new_entry_in(&staging, "svc", "usr").unwrap();
+        entry.set_password("staging").unwrap();
+        new_entry_in(&production, "svc", "usr")
+            .unwrap()
+            .set_password("production")
+            .unwrap();
+        new_entry_in(&inner, "svc", "usr")
+            .unwrap()
+            .set_password("bare")
+            .unwrap();
+        assert_eq!(entry.get_specifiers().unwrap().0, "svc");
+        let direct = new_entry_in(&inner, "staging:svc", "usr").unwrap();
+        assert_eq!(direct.get_password().unwrap(), "staging");
+        let found = staging.search(&HashMap::new()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].get_specifiers(),
+            Some(("svc".to_string(), "usr".to_string()))
+        );
+        assert_eq!(found[0].get_password().unwrap(), "staging");
+        let found = production
+            .search(&HashMap::from([("service", "svc"), ("user", "usr")]))
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].get_password().unwrap(), "production");
+        assert_eq!(inner.search(&HashMap::new()).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,169 @@
+//! Store profiles read from a configuration file.
+//!
+//! A configuration file is written in [RON](https://github.com/ron-rs/ron) (the same
+//! format used by the backing file of the sample store), and defines any number of
+//! named profiles. Each profile gives a store name, the modifiers to build the store
+//! with, and an optional prefix for service names. For example:
+//!
+//! ```ron
+//! (
+//!     profiles: {
+//!         "production": (
+//!             store: "keyutils",
+//!             modifiers: { "prefix": "prod:" },
+//!             service_prefix: Some("prod-"),
+//!         ),
+//!         "test": (store: "sample"),
+//!     },
+//! )
+//! ```
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use keyring_core::{Error, Result};
+
+use super::{canonical_name, use_parsed_store_spec};
+
+/// A named store configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreProfile {
+    /// The name of the store, as given to [use_named_store](super::use_named_store).
+    pub store: String,
+    /// The modifiers to build the store with.
+    #[serde(default)]
+    pub modifiers: HashMap<String, String>,
+    /// A prefix to put in front of the service name of every entry.
+    #[serde(default)]
+    pub service_prefix: Option<String>,
+}
+
+impl StoreProfile {
+    /// Return the service name to use in this profile for the given service.
+    pub fn service_name(&self, service: &str) -> String {
+        match &self.service_prefix {
+            Some(prefix) => format!("{prefix}{service}"),
+            None => service.to_string(),
+        }
+    }
+}
+
+/// The contents of a store configuration file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreConfig {
+    /// The profiles, by name.
+    #[serde(default)]
+    pub profiles: HashMap<String, StoreProfile>,
+}
+
+impl StoreConfig {
+    /// Parse a configuration from a RON string.
+    ///
+    /// Gives an `Invalid` error if the string is not a valid configuration.
+    pub fn parse(content: &str) -> Result<Self> {
+        ron::de::from_str(content).map_err(|err| {
+            Error::Invalid(
+                "store configuration".to_string(),
+                format!("is not valid: {err}"),
+            )
+        })
+    }
+
+    /// Load a configuration from a RON file.
+    ///
+    /// Gives an `Invalid` error if the file can't be read or is not a valid configuration.
+    pub fn load(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| Error::Invalid(path.to_string(), err.to_string()))?;
+        Self::parse(&content).map_err(|err| match err {
+            Error::Invalid(_, reason) => Error::Invalid(path.to_string(), reason),
+            err => err,
+        })
+    }
+
+    /// Look up a profile by name.
+    ///
+    /// Gives an `Invalid` error if there is no such profile.
+    pub fn profile(&self, name: &str) -> Result<&StoreProfile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let mut known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            known.sort();
+            Error::Invalid(
+                name.to_string(),
+                format!("is not a profile; must be one of: {}", known.join(", ")),
+            )
+        })
+    }
+}
+
+/// Set the default store as described by a profile in a configuration file.
+///
+/// The store named by the profile is chosen as by [use_named_store](super::use_named_store)
+/// if the profile has no modifiers, and as by
+/// [use_named_store_with_modifiers](super::use_named_store_with_modifiers) otherwise.
+///
+/// Returns the profile, so the caller can apply its service prefix to entries.
+pub fn use_store_profile(path: &str, profile: &str) -> Result<StoreProfile> {
+    let config = StoreConfig::load(path)?;
+    let mut profile = config.profile(profile)?.clone();
+    profile.store = canonical_name(&profile.store);
+    use_parsed_store_spec(&profile.store, &profile.modifiers)?;
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"(
+        profiles: {
+            "production": (
+                store: "keyutils",
+                modifiers: { "prefix": "prod:" },
+                service_prefix: Some("prod-"),
+            ),
+            "test": (store: "Sample", modifiers: { "persist": "false" }),
+        },
+    )"#;
+
+    #[test]
+    fn test_parse_config() {
+        let config = StoreConfig::parse(CONFIG).unwrap();
+        let production = config.profile("production").unwrap();
+        assert_eq!(production.store, "keyutils");
+        assert_eq!(
+            production.modifiers.get("prefix"),
+            Some(&"prod:".to_string())
+        );
+        assert_eq!(production.service_name("svc"), "prod-svc");
+        let test = config.profile("test").unwrap();
+        assert_eq!(test.modifiers.len(), 1);
+        assert_eq!(test.service_name("svc"), "svc");
+        match config.profile("staging") {
+            Err(Error::Invalid(name, reason)) => {
+                assert_eq!(name, "staging");
+                assert!(reason.ends_with("production, test"));
+            }
+            other => panic!("Missing profile was found: {other:?}"),
+        }
+        assert!(StoreConfig::parse("(profiles: [])").is_err());
+    }
+
+    #[test]
+    fn test_use_store_profile() {
+        let _guard = super::super::tests::DEFAULT_STORE_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let _restore = crate::cli::DefaultStoreGuard::capture();
+        let name = format!("keyring-test-profiles-{}.ron", fastrand::u64(..));
+        let path = std::env::temp_dir().join(name);
+        let path = path.to_str().unwrap();
+        std::fs::write(path, CONFIG).unwrap();
+        let profile = use_store_profile(path, "test").unwrap();
+        assert_eq!(profile.store, "sample");
+        assert!(use_store_profile(path, "staging").is_err());
+        std::fs::remove_file(path).unwrap();
+        assert!(use_store_profile(path, "test").is_err());
+    }
+}
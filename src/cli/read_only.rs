@@ -0,0 +1,171 @@
+//! A store wrapper that refuses to change the wrapped store.
+//!
+//! Processes that should only ever read credentials can wrap their store in a
+//! [ReadOnlyStore], so that a bug which tries to set, update, or delete a
+//! credential fails with a `NotSupportedByStore` error instead of changing
+//! the store. Reads and searches are passed through.
+//!
+//! Any named store can be wrapped by giving it the `read-only=true` modifier,
+//! and the current default store can be wrapped with
+//! [make_default_store_read_only](super::make_default_store_read_only).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+/// A store that only allows reads from another store. See the module docs.
+#[derive(Debug)]
+pub struct ReadOnlyStore {
+    inner: Arc<CredentialStore>,
+}
+
+impl ReadOnlyStore {
+    /// Wrap a store so that it can't be changed.
+    pub fn new(inner: Arc<CredentialStore>) -> Arc<Self> {
+        Arc::new(ReadOnlyStore { inner })
+    }
+
+    fn wrap(&self, inner: Entry) -> Entry {
+        Entry::new_with_credential(Arc::new(ReadOnlyCredential { inner }))
+    }
+}
+
+impl CredentialStoreApi for ReadOnlyStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        Ok(self.wrap(self.inner.build(service, user, mods)?))
+    }
+
+    /// See the API docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        Ok(self
+            .inner
+            .search(spec)?
+            .into_iter()
+            .map(|e| self.wrap(e))
+            .collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [ReadOnlyStore].
+#[derive(Debug)]
+struct ReadOnlyCredential {
+    inner: Entry,
+}
+
+fn refuse(operation: &str) -> Error {
+    Error::NotSupportedByStore(format!(
+        "The store is read-only, so credentials can't be {operation}"
+    ))
+}
+
+impl CredentialApi for ReadOnlyCredential {
+    fn set_secret(&self, _: &[u8]) -> Result<()> {
+        Err(refuse("set"))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.inner.get_secret()
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.inner.get_attributes()
+    }
+
+    fn update_attributes(&self, _: &HashMap<&str, &str>) -> Result<()> {
+        Err(refuse("updated"))
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        Err(refuse("deleted"))
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        Ok(Some(Arc::new(ReadOnlyCredential {
+            inner: self.inner.get_credential()?,
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    #[test]
+    fn test_read_only() {
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let store: Arc<CredentialStore> = ReadOnlyStore::new(inner.clone());
+        new_entry_in(&inner, "svc", "usr")
+            .unwrap()
+            .set_password("readable")
+            .unwrap();
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "readable");
+        assert!(matches!(
+            entry.set_password("changed"),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let attrs = HashMap::from([("comment", "changed")]);
+        assert!(matches!(
+            entry.update_attributes(&attrs),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let found = store.search(&HashMap::new()).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(matches!(
+            found[0].delete_credential(),
+            Err(Error::NotSupportedByStore(_))
+        ));
+        let wrapper = entry.get_credential().unwrap();
+        assert!(wrapper.set_password("changed").is_err());
+        assert_eq!(wrapper.get_password().unwrap(), "readable");
+    }
+}
@@ -0,0 +1,474 @@
+//! A store wrapper that retries operations that fail transiently.
+//!
+//! Some stores fail now and then for reasons that go away by themselves:
+//! Secret Service calls, for example, can give `PlatformFailure` or
+//! `NoStorageAccess` errors while the keyring daemon is starting or a
+//! collection is being unlocked. Wrapping such a store in a [RetryingStore]
+//! makes each operation (including building entries and searching) be tried
+//! again, after an exponentially growing delay, as long as it fails with an
+//! error that the [RetryPolicy] counts as retryable.
+//!
+//! Each retry is logged at debug level, and if the store is also audited
+//! (see [AuditingStore](super::AuditingStore)) each audit record says how many
+//! attempts the operation took.
+//!
+//! Because a failed attempt might have had an effect (a deletion that
+//! succeeded but reported a failure, say), the errors given by retried
+//! operations can differ from those the first attempt would have given.
+//!
+//! Operations that time out (see [TimeoutStore](super::TimeoutStore)) are
+//! never retried, whatever the policy says about `NoStorageAccess` errors.
+//!
+//! Any named store can be wrapped by giving it the `retry-attempts` modifier
+//! (and, optionally, `retry-backoff-ms`, `retry-max-backoff-ms`, and `retry-on`).
+
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+};
+
+use super::timeout::is_timeout;
+
+/// The error variants that can be named in [RetryPolicy::retryable].
+pub const ERROR_VARIANTS: [&str; 11] = [
+    "PlatformFailure",
+    "NoStorageAccess",
+    "NoEntry",
+    "BadEncoding",
+    "BadDataFormat",
+    "BadStoreFormat",
+    "TooLong",
+    "Invalid",
+    "Ambiguous",
+    "NoDefaultStore",
+    "NotSupportedByStore",
+];
+
+/// The name of an error's variant, as used in audit records and retry policies.
+pub(super) fn variant_name(err: &Error) -> &'static str {
+    match err {
+        Error::PlatformFailure(_) => "PlatformFailure",
+        Error::NoStorageAccess(_) => "NoStorageAccess",
+        Error::NoEntry => "NoEntry",
+        Error::BadEncoding(_) => "BadEncoding",
+        Error::BadDataFormat(_, _) => "BadDataFormat",
+        Error::BadStoreFormat(_) => "BadStoreFormat",
+        Error::TooLong(_, _) => "TooLong",
+        Error::Invalid(_, _) => "Invalid",
+        Error::Ambiguous(_) => "Ambiguous",
+        Error::NoDefaultStore => "NoDefaultStore",
+        Error::NotSupportedByStore(_) => "NotSupportedByStore",
+        _ => "Unknown",
+    }
+}
+
+/// When and how often a [RetryingStore] retries a failed operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The most times an operation is tried (including the first time).
+    pub max_attempts: u32,
+    /// The delay before the first retry; each later retry waits twice as long.
+    pub initial_backoff: Duration,
+    /// The longest delay between retries.
+    pub max_backoff: Duration,
+    /// The names of the error variants (such as `PlatformFailure`) that are retried.
+    pub retryable: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, backing off from 100ms, retrying
+    /// `PlatformFailure` and `NoStorageAccess` errors.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retryable: vec!["PlatformFailure".to_string(), "NoStorageAccess".to_string()],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Parse a list of retryable error variants separated by `+`,
+    /// such as `PlatformFailure+NoStorageAccess`.
+    ///
+    /// (The separator isn't a comma because commas separate store modifiers.)
+    ///
+    /// Gives an `Invalid` error if any of them isn't one of the [ERROR_VARIANTS].
+    pub fn parse_retryable(list: &str) -> Result<Vec<String>> {
+        let mut retryable = Vec::new();
+        for name in list.split('+').filter(|name| !name.is_empty()) {
+            if !ERROR_VARIANTS.contains(&name) {
+                return Err(Error::Invalid(
+                    name.to_string(),
+                    format!("must be one of: {}", ERROR_VARIANTS.join(", ")),
+                ));
+            }
+            retryable.push(name.to_string());
+        }
+        Ok(retryable)
+    }
+
+    /// Whether the policy retries an error.
+    ///
+    /// Timeouts (see [is_timeout]) are never retried, even though they are
+    /// `NoStorageAccess` errors: the operation that timed out may still be
+    /// running, and a store that hangs once is likely to hang again.
+    pub fn is_retryable(&self, err: &Error) -> bool {
+        if is_timeout(err) {
+            return false;
+        }
+        let name = variant_name(err);
+        self.retryable.iter().any(|r| r == name)
+    }
+
+    /// The delay before the given retry (numbered from 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Run an operation, retrying it as the policy allows.
+    fn run<T>(&self, operation: &str, op: impl Fn() -> Result<T>) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            let result = op();
+            match result {
+                Err(err) if attempt < self.max_attempts && self.is_retryable(&err) => {
+                    let delay = self.backoff(attempt);
+                    log::debug!(
+                        "retrying {operation} in {delay:?} after attempt {attempt} of {} failed: {err:?}",
+                        self.max_attempts
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => {
+                    LAST_ATTEMPTS.set(Some(attempt));
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+thread_local! {
+    /// How many attempts the last retried operation on this thread took.
+    static LAST_ATTEMPTS: Cell<Option<u32>> = const { Cell::new(None) };
+}
+
+/// Take (and forget) how many attempts the last retried operation on this thread took.
+///
+/// Only audit records report this, so without the `audit` feature it's only used in tests.
+#[cfg_attr(not(feature = "audit"), allow(dead_code))]
+pub(super) fn take_attempts() -> Option<u32> {
+    LAST_ATTEMPTS.take()
+}
+
+/// A store that retries failed operations on another store. See the module docs.
+#[derive(Debug)]
+pub struct RetryingStore {
+    inner: Arc<CredentialStore>,
+    policy: Arc<RetryPolicy>,
+}
+
+impl RetryingStore {
+    /// Wrap a store so that its failed operations are retried according to a policy.
+    pub fn new(inner: Arc<CredentialStore>, policy: RetryPolicy) -> Arc<Self> {
+        Arc::new(RetryingStore {
+            inner,
+            policy: Arc::new(policy),
+        })
+    }
+
+    /// The store's retry policy.
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    fn wrap(&self, inner: Entry) -> Entry {
+        Entry::new_with_credential(Arc::new(RetryingCredential {
+            inner,
+            policy: self.policy.clone(),
+        }))
+    }
+}
+
+impl CredentialStoreApi for RetryingStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let entry = self
+            .policy
+            .run("build", || self.inner.build(service, user, mods))?;
+        Ok(self.wrap(entry))
+    }
+
+    /// See the API docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let found = self.policy.run("search", || self.inner.search(spec))?;
+        Ok(found.into_iter().map(|e| self.wrap(e)).collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [RetryingStore].
+#[derive(Debug)]
+struct RetryingCredential {
+    inner: Entry,
+    policy: Arc<RetryPolicy>,
+}
+
+impl CredentialApi for RetryingCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        self.policy
+            .run("set_secret", || self.inner.set_secret(secret))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.policy.run("get_secret", || self.inner.get_secret())
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.policy
+            .run("get_attributes", || self.inner.get_attributes())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        self.policy.run("update_attributes", || {
+            self.inner.update_attributes(attributes)
+        })
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.policy
+            .run("delete_credential", || self.inner.delete_credential())
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let inner = self
+            .policy
+            .run("get_credential", || self.inner.get_credential())?;
+        Ok(Some(Arc::new(RetryingCredential {
+            inner,
+            policy: self.policy.clone(),
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A credential that fails a given number of times before succeeding.
+    #[derive(Debug)]
+    struct Flaky {
+        failures: AtomicU32,
+    }
+
+    impl CredentialApi for Flaky {
+        fn set_secret(&self, _: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_secret(&self) -> Result<Vec<u8>> {
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                Err(Error::NoStorageAccess("locked".into()))
+            } else {
+                Ok(b"secret".to_vec())
+            }
+        }
+
+        fn delete_credential(&self) -> Result<()> {
+            Err(Error::NoEntry)
+        }
+
+        fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+            Ok(None)
+        }
+
+        fn get_specifiers(&self) -> Option<(String, String)> {
+            None
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    fn flaky(failures: u32, policy: RetryPolicy) -> Entry {
+        let inner = Entry::new_with_credential(Arc::new(Flaky {
+            failures: AtomicU32::new(failures),
+        }));
+        Entry::new_with_credential(Arc::new(RetryingCredential {
+            inner,
+            policy: Arc::new(policy),
+        }))
+    }
+
+    #[test]
+    fn test_retries() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(flaky(2, policy.clone()).get_secret().unwrap(), b"secret");
+        assert_eq!(take_attempts(), Some(3));
+        assert!(matches!(
+            flaky(3, policy.clone()).get_secret(),
+            Err(Error::NoStorageAccess(_))
+        ));
+        assert!(matches!(
+            flaky(0, policy.clone()).delete_credential(),
+            Err(Error::NoEntry)
+        ));
+        assert_eq!(take_attempts(), Some(1));
+        let strict = RetryPolicy {
+            retryable: RetryPolicy::parse_retryable("PlatformFailure").unwrap(),
+            ..policy
+        };
+        assert!(flaky(1, strict).get_secret().is_err());
+        assert!(RetryPolicy::parse_retryable("Flaky").is_err());
+    }
+
+    /// A store whose credentials count their reads, each of which is slow.
+    #[derive(Debug)]
+    struct SlowStore {
+        reads: Arc<AtomicU32>,
+    }
+
+    #[derive(Debug)]
+    struct Slow {
+        reads: Arc<AtomicU32>,
+    }
+
+    impl CredentialStoreApi for SlowStore {
+        fn vendor(&self) -> String {
+            "slow".to_string()
+        }
+
+        fn id(&self) -> String {
+            "slow".to_string()
+        }
+
+        fn build(&self, _: &str, _: &str, _: Option<&HashMap<&str, &str>>) -> Result<Entry> {
+            Ok(Entry::new_with_credential(Arc::new(Slow {
+                reads: self.reads.clone(),
+            })))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    impl CredentialApi for Slow {
+        fn set_secret(&self, _: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_secret(&self) -> Result<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(b"secret".to_vec())
+        }
+
+        fn delete_credential(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+            Ok(None)
+        }
+
+        fn get_specifiers(&self) -> Option<(String, String)> {
+            None
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_timeouts_are_not_retried() {
+        let reads = Arc::new(AtomicU32::new(0));
+        let store: Arc<CredentialStore> = Arc::new(SlowStore {
+            reads: reads.clone(),
+        });
+        let wrapping = HashMap::from([
+            ("timeout-ms", "20"),
+            ("retry-attempts", "3"),
+            ("retry-backoff-ms", "1"),
+        ]);
+        let store = super::super::wrap_store(store, &wrapping).unwrap();
+        let entry = store.build("svc", "usr", None).unwrap();
+        let err = entry.get_secret().unwrap_err();
+        assert!(is_timeout(&err));
+        assert_eq!(take_attempts(), Some(1));
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            ..RetryPolicy::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,288 @@
+//! A store wrapper that gives up on operations that take too long.
+//!
+//! Some stores can block indefinitely: a Secret Service call that pops up
+//! an unlock prompt nobody answers, for example, never returns. Wrapping
+//! such a store in a [TimeoutStore] runs each of its operations (including
+//! building entries and searching) on a separate thread, and if the operation
+//! doesn't finish within the timeout gives a `NoStorageAccess` error wrapping
+//! an [OperationTimedOut] error (see [is_timeout]).
+//!
+//! An operation that times out is abandoned, not cancelled: its thread keeps
+//! running until the wrapped store returns, and any change it makes then
+//! still takes effect.
+//!
+//! Any named store can be wrapped by giving it the `timeout-ms` modifier, and
+//! every store built by name can be given a timeout by calling
+//! [set_operation_timeout](super::set_operation_timeout).
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use zeroize::Zeroizing;
+
+use super::internalize;
+
+use keyring_core::{
+    Credential, CredentialPersistence, CredentialStore, Entry, Error, Result,
+    api::{CredentialApi, CredentialStoreApi},
+    attributes::externalize_attributes,
+};
+
+/// The error wrapped by the `NoStorageAccess` error of an operation that timed out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationTimedOut {
+    /// The operation that timed out, such as `get_secret`.
+    pub operation: String,
+    /// How long the operation was given.
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for OperationTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} didn't finish within {:?}",
+            self.operation, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for OperationTimedOut {}
+
+/// Whether an error was given because an operation timed out.
+pub fn is_timeout(err: &Error) -> bool {
+    match err {
+        Error::NoStorageAccess(inner) => inner.downcast_ref::<OperationTimedOut>().is_some(),
+        _ => false,
+    }
+}
+
+/// Run an operation on its own thread, giving up on it after the timeout.
+fn run<T: Send + 'static>(
+    timeout: Duration,
+    operation: &str,
+    op: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name(format!("keyring-{operation}"))
+        .spawn(move || {
+            // the receiver is gone if the operation timed out
+            let _ = sender.send(op());
+        })
+        .map_err(|err| Error::PlatformFailure(err.into()))?;
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            Err(Error::NoStorageAccess(Box::new(OperationTimedOut {
+                operation: operation.to_string(),
+                timeout,
+            })))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(Error::PlatformFailure(
+            format!("{operation} panicked").into(),
+        )),
+    }
+}
+
+/// A store that limits how long operations on another store can take. See the module docs.
+#[derive(Debug)]
+pub struct TimeoutStore {
+    inner: Arc<CredentialStore>,
+    timeout: Duration,
+}
+
+impl TimeoutStore {
+    /// Wrap a store so that its operations time out after the given time.
+    pub fn new(inner: Arc<CredentialStore>, timeout: Duration) -> Arc<Self> {
+        Arc::new(TimeoutStore { inner, timeout })
+    }
+
+    /// How long each operation is given.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn wrap(&self, inner: Entry) -> Entry {
+        Entry::new_with_credential(Arc::new(TimeoutCredential {
+            inner: Arc::new(inner),
+            timeout: self.timeout,
+        }))
+    }
+}
+
+impl CredentialStoreApi for TimeoutStore {
+    /// See the API docs.
+    ///
+    /// This is the vendor of the wrapped store.
+    fn vendor(&self) -> String {
+        self.inner.vendor()
+    }
+
+    /// See the API docs.
+    ///
+    /// This is the id of the wrapped store.
+    fn id(&self) -> String {
+        self.inner.id()
+    }
+
+    /// See the API docs.
+    fn build(
+        &self,
+        service: &str,
+        user: &str,
+        mods: Option<&HashMap<&str, &str>>,
+    ) -> Result<Entry> {
+        let inner = self.inner.clone();
+        let (service, user) = (service.to_string(), user.to_string());
+        let mods = mods.map(externalize_attributes);
+        let entry = run(self.timeout, "build", move || {
+            let mods = mods.as_ref().map(|mods| internalize(Some(mods)));
+            inner.build(&service, &user, mods.as_ref())
+        })?;
+        Ok(self.wrap(entry))
+    }
+
+    /// See the API docs.
+    fn search(&self, spec: &HashMap<&str, &str>) -> Result<Vec<Entry>> {
+        let inner = self.inner.clone();
+        let spec = externalize_attributes(spec);
+        let found = run(self.timeout, "search", move || {
+            inner.search(&internalize(Some(&spec)))
+        })?;
+        Ok(found.into_iter().map(|e| self.wrap(e)).collect())
+    }
+
+    /// See the API docs.
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    /// See the API docs.
+    fn persistence(&self) -> CredentialPersistence {
+        self.inner.persistence()
+    }
+
+    /// See the API docs.
+    fn debug_fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A credential in a [TimeoutStore].
+#[derive(Debug)]
+struct TimeoutCredential {
+    inner: Arc<Entry>,
+    timeout: Duration,
+}
+
+impl TimeoutCredential {
+    fn run<T: Send + 'static>(
+        &self,
+        operation: &str,
+        op: impl FnOnce(&Entry) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let inner = self.inner.clone();
+        run(self.timeout, operation, move || op(&inner))
+    }
+}
+
+impl CredentialApi for TimeoutCredential {
+    fn set_secret(&self, secret: &[u8]) -> Result<()> {
+        let secret = Zeroizing::new(secret.to_vec());
+        self.run("set_secret", move |e| e.set_secret(&secret))
+    }
+
+    fn get_secret(&self) -> Result<Vec<u8>> {
+        self.run("get_secret", |e| e.get_secret())
+    }
+
+    fn get_attributes(&self) -> Result<HashMap<String, String>> {
+        self.run("get_attributes", |e| e.get_attributes())
+    }
+
+    fn update_attributes(&self, attributes: &HashMap<&str, &str>) -> Result<()> {
+        let attributes = externalize_attributes(attributes);
+        self.run("update_attributes", move |e| {
+            e.update_attributes(&internalize(Some(&attributes)))
+        })
+    }
+
+    fn delete_credential(&self) -> Result<()> {
+        self.run("delete_credential", |e| e.delete_credential())
+    }
+
+    fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+        let inner = self.run("get_credential", |e| e.get_credential())?;
+        Ok(Some(Arc::new(TimeoutCredential {
+            inner: Arc::new(inner),
+            timeout: self.timeout,
+        })))
+    }
+
+    fn get_specifiers(&self) -> Option<(String, String)> {
+        self.inner.get_specifiers()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{build_sample_store, new_entry_in};
+
+    /// A credential whose reads never finish.
+    #[derive(Debug)]
+    struct Hanging;
+
+    impl CredentialApi for Hanging {
+        fn set_secret(&self, _: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_secret(&self) -> Result<Vec<u8>> {
+            std::thread::sleep(Duration::from_secs(3600));
+            Ok(Vec::new())
+        }
+
+        fn delete_credential(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn get_credential(&self) -> Result<Option<Arc<Credential>>> {
+            Ok(None)
+        }
+
+        fn get_specifiers(&self) -> Option<(String, String)> {
+            None
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let hanging = Entry::new_with_credential(Arc::new(TimeoutCredential {
+            inner: Arc::new(Entry::new_with_credential(Arc::new(Hanging))),
+            timeout: Duration::from_millis(20),
+        }));
+        let err = hanging.get_secret().unwrap_err();
+        assert!(is_timeout(&err));
+        hanging.set_secret(b"quick").unwrap();
+        let inner = build_sample_store(&HashMap::new()).unwrap();
+        let store: Arc<CredentialStore> = TimeoutStore::new(inner, Duration::from_secs(10));
+        let entry = new_entry_in(&store, "svc", "usr").unwrap();
+        entry.set_password("in time").unwrap();
+        assert_eq!(entry.get_password().unwrap(), "in time");
+        assert_eq!(store.search(&HashMap::new()).unwrap().len(), 1);
+        entry.delete_credential().unwrap();
+        assert!(matches!(entry.get_password(), Err(Error::NoEntry)));
+    }
+}
@@ -13,6 +13,14 @@
 //! example app, the `rust-native-keyring` Python module, and the `keyring-demo` cross-platform
 //! application access all available credential stores on all platforms. See the [cli]
 //! module docs for details, and the README of this crate for more information.
+//! Enabling the `async` feature (which implies `cli`) adds async versions of the
+//! `cli` module's entry and store-choosing functions, such as `AsyncEntry`.
+//! Enabling the `kdbx` feature (which also implies `cli`) adds the `kdbx` store,
+//! which keeps credentials in a KeePass database.
+//! The `archive` (encrypted export and import), `audit` (audit logs), `env` (the
+//! environment-variable store), and `profile` (store profiles) features each imply
+//! `cli` and add the named part of it, and the `serde` feature makes the `cli`
+//! module's `StoreInfo` serializable.
 //!
 //! Note that *neither* of these modes are either useful for or meant for use by
 //! applications which want to control which credential stores they use on which